@@ -1,8 +1,19 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// 思源黑体（Noto Sans SC，SIL OFL 1.1），RL-CLIA 标签文字的兜底中文字体。
+const FONT_NAME: &str = "NotoSansSC-Regular.otf";
+const FONT_URL: &str =
+    "https://github.com/notofonts/noto-cjk/raw/main/Sans/SubsetOTF/SC/NotoSansSC-Regular.otf";
+
 fn main() {
     embed_resource::compile_for("./icon.rc", ["AbbottBarcodeGeneration"]);
     embed_resource::compile_for("./lotid-icon.rc", ["LotID-Codec"]);
     embed_resource::compile_for("./LiteCrypt-icon", ["LiteCrypt"]);
 
+    // RL-CLIA 标签文字的兜底中文字体，必须编译进二进制。
+    bundle_font();
+
     // ui/main.slint 作为统一入口，引入导出 了 barcode.slint 和 lotid.slint，
     // 一次编译即可将所有组件（BarcodeWindow、LotIdWindow）都写入生成代码，
     // 各 binary 的 include_modules!() 均能引用各自所需的组件。
@@ -12,3 +23,41 @@ fn main() {
     )
     .unwrap();
 }
+
+/// 把字体放到 OUT_DIR 供 `include_bytes!` 使用：优先取 assets/fonts/ 下的文件，
+/// 否则从 `RLCLIA_FONT_URL`（默认 [`FONT_URL`]）下载。拿不到字体时编译失败，
+/// 不会生成缺少中文字体的程序。
+fn bundle_font() {
+    let local = Path::new("assets/fonts").join(FONT_NAME);
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join(FONT_NAME);
+    println!("cargo::rerun-if-changed={}", local.display());
+    println!("cargo::rerun-if-env-changed=RLCLIA_FONT_URL");
+
+    let url = std::env::var("RLCLIA_FONT_URL").unwrap_or_else(|_| FONT_URL.to_string());
+    if local.exists() {
+        std::fs::copy(&local, &out).expect("复制内置字体失败");
+    } else if !is_otf(&out) {
+        // Windows 10 起自带 curl.exe，Linux / macOS 亦然。
+        let _ = std::process::Command::new("curl")
+            .args(["-fsSL", "--retry", "3", "-o"])
+            .arg(&out)
+            .arg(&url)
+            .status();
+    }
+    if !is_otf(&out) {
+        let _ = std::fs::remove_file(&out);
+        panic!(
+            "缺少内置字体 {FONT_NAME}：下载 {url} 失败。\
+             请手动下载后放到 assets/fonts/，或用 RLCLIA_FONT_URL 指定镜像地址"
+        );
+    }
+}
+
+/// OpenType（CFF）字体文件以 `OTTO` 开头。
+fn is_otf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && &magic == b"OTTO"
+}
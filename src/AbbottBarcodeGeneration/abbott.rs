@@ -1,6 +1,11 @@
-use crate::barcode::{gray_to_slint_image, make_barcode_image, save_png_300dpi};
-use crate::config::Config;
+use crate::barcode::{format_name, gray_to_slint_image, make_barcode_image, save_image};
+use crate::config::{Config, ImageExport};
 use anyhow::Result;
+use rust_learning::audit::{AuditLog, AuditRecord};
+use rust_learning::manifest::{
+    Manifest, ManifestLabel, SiteKey, folder_manifest_path, manifest_path_for, parameters,
+    write_manifest,
+};
 use serde::{Deserialize, Serialize};
 use std::fs;
 
@@ -272,13 +277,21 @@ pub fn build_long_content(
 
 pub struct AbbottBarcodeItem {
     pub label: String,
+    /// Project and lot (control number with suffix) the barcode was issued
+    /// under, for the audit trail.
+    pub project: String,
+    pub lot: String,
+    /// SN of the reagent slot the barcode belongs to.
+    pub serial: String,
     pub content: String,
     pub slint_image: slint::Image,
     pub gray_image: image::GrayImage,
+    /// Settings the barcode was generated with, for ZPL export.
+    pub config: Config,
 }
 
 /// CompactPDF417, EC6, 2 columns, rotated 90°, 7.4×1.8 cm
-fn short_config(content: &str) -> Config {
+fn short_config(content: &str, dpi: u32) -> Config {
     Config {
         content: content.to_string(),
         format_index: 0,  // CompactPDF417
@@ -288,13 +301,16 @@ fn short_config(content: &str) -> Config {
         eclevel_index: 6,
         width_cm: 7.4,
         height_cm: 1.8,
+        dpi,
+        exact_modules: false,
+        export: ImageExport::default(),
         abbott_mode: false,
         abbott_project_index: 0,
     }
 }
 
 /// Standard PDF417, 4.0×2.0 cm
-fn long_config(content: &str) -> Config {
+fn long_config(content: &str, dpi: u32) -> Config {
     Config {
         content: content.to_string(),
         format_index: 1,  // PDF417
@@ -304,11 +320,38 @@ fn long_config(content: &str) -> Config {
         eclevel_index: 2, // eclevel=2
         width_cm: 4.0,
         height_cm: 2.0,
+        dpi,
+        exact_modules: false,
+        export: ImageExport::default(),
         abbott_mode: false,
         abbott_project_index: 0,
     }
 }
 
+/// Name of this tool in the audit trail.
+pub const TOOL: &str = "AbbottBarcodeGeneration";
+
+fn make_item(
+    label: String,
+    project: &str,
+    lot: &str,
+    serial: String,
+    content: String,
+    config: Config,
+) -> Result<AbbottBarcodeItem> {
+    let result = make_barcode_image(&config)?;
+    Ok(AbbottBarcodeItem {
+        label,
+        project: project.to_string(),
+        lot: lot.to_string(),
+        serial,
+        content,
+        slint_image: gray_to_slint_image(&result.gray_image),
+        gray_image: result.gray_image,
+        config,
+    })
+}
+
 /// Generate all barcodes for an Abbott project.
 ///
 /// - For reagents with `generates_long = true`: produce long barcode + short barcode
@@ -316,12 +359,15 @@ fn long_config(content: &str) -> Config {
 ///
 /// `sns` maps 1:1 to `project.reagents` by index.
 /// `project_bits_override`: if non-empty, overrides reagent's project_bits for long barcodes.
+/// Images are sized for a `dpi` printer. Nothing is audited here; see
+/// [`record_export`].
 pub fn generate_abbott_barcodes(
     project: &AbbottProject,
     sns: &[String],
     control_no_number: &str,
     expiry: &str,
     project_bits_override: &str,
+    dpi: u32,
 ) -> Result<Vec<AbbottBarcodeItem>> {
     let expiry_encoded = encode_expiry(expiry, &project.expiry_format);
     let bits_override = if project_bits_override.is_empty() {
@@ -329,6 +375,7 @@ pub fn generate_abbott_barcodes(
     } else {
         Some(project_bits_override)
     };
+    let lot = format!("{}{}", control_no_number, project.control_no_suffix);
     let mut items = Vec::new();
 
     for (i, reagent) in project.reagents.iter().enumerate() {
@@ -344,39 +391,302 @@ pub fn generate_abbott_barcodes(
                 &expiry_encoded,
                 bits_override,
             );
-            let long_result = make_barcode_image(&long_config(&long_content))?;
-            items.push(AbbottBarcodeItem {
-                label: format!("{} 长码", reagent.name),
-                content: long_content,
-                slint_image: gray_to_slint_image(&long_result.gray_image),
-                gray_image: long_result.gray_image,
-            });
+            let config = long_config(&long_content, dpi);
+            items.push(make_item(
+                format!("{} 长码", reagent.name),
+                &project.name,
+                &lot,
+                sn.to_string(),
+                long_content,
+                config,
+            )?);
         }
 
         // Short barcode
         if reagent.generates_short {
             let short_content =
                 build_short_content(sn, reagent, control_no_number, &project.control_no_suffix);
-            let short_result = make_barcode_image(&short_config(&short_content))?;
-            items.push(AbbottBarcodeItem {
-                label: format!("{} 短码", reagent.name),
-                content: short_content,
-                slint_image: gray_to_slint_image(&short_result.gray_image),
-                gray_image: short_result.gray_image,
-            });
+            let config = short_config(&short_content, dpi);
+            items.push(make_item(
+                format!("{} 短码", reagent.name),
+                &project.name,
+                &lot,
+                sn.to_string(),
+                short_content,
+                config,
+            )?);
         }
     }
 
     Ok(items)
 }
 
-/// Export all generated barcodes to a directory.
-pub fn export_abbott_barcodes(items: &[AbbottBarcodeItem], dir: &std::path::Path) -> Result<()> {
+/// Append one audit row per barcode of a freshly exported batch. Called only
+/// after the export succeeded, so previews that were never written out leave
+/// no trace in the log.
+pub fn record_export(items: &[AbbottBarcodeItem]) -> Result<()> {
+    let records: Vec<AuditRecord> = items
+        .iter()
+        .map(|item| AuditRecord {
+            project: item.project.clone(),
+            lot: item.lot.clone(),
+            serial: item.serial.clone(),
+            plaintext: item.content.clone(),
+            content: item.content.clone(),
+            ..AuditRecord::now(TOOL, &item.label)
+        })
+        .collect();
+    AuditLog::default()
+        .append(&records)
+        .map_err(anyhow::Error::msg)
+}
+
+/// Rebuild an Abbott barcode from its audit record at `dpi`.
+pub fn regenerate(record: &AuditRecord, dpi: u32) -> Result<AbbottBarcodeItem> {
+    let config = if record.label_type.ends_with("长码") {
+        long_config(&record.content, dpi)
+    } else if record.label_type.ends_with("短码") {
+        short_config(&record.content, dpi)
+    } else {
+        anyhow::bail!("不是雅培项目条码: {}", record.label_type);
+    };
+    make_item(
+        record.label_type.clone(),
+        &record.project,
+        &record.lot,
+        record.serial.clone(),
+        record.content.clone(),
+        config,
+    )
+}
+
+/// Export all generated barcodes to a directory in the `export` image format,
+/// followed by a manifest of the files signed with `key`.
+pub fn export_abbott_barcodes(
+    items: &[AbbottBarcodeItem],
+    dir: &std::path::Path,
+    export: &ImageExport,
+    key: &SiteKey,
+) -> Result<()> {
     fs::create_dir_all(dir)?;
+    let mut manifest = Manifest::new(TOOL);
     for (i, item) in items.iter().enumerate() {
         let safe_label = item.label.replace(' ', "_").replace('/', "_");
-        let filename = format!("{:02}_{}.png", i + 1, safe_label);
-        save_png_300dpi(&item.gray_image, dir.join(&filename))?;
+        let filename = format!("{:02}_{}.{}", i + 1, safe_label, export.extension());
+        let path = dir.join(&filename);
+        save_image(&item.gray_image, &item.config, &path, export)?;
+        let label = ManifestLabel {
+            serial: item.serial.clone(),
+            content: item.content.clone(),
+        };
+        let params = image_params(&item.label, &item.config, export);
+        manifest
+            .add_file(&path, vec![label], parameters(&params))
+            .map_err(anyhow::Error::msg)?;
     }
+    write_manifest(manifest, &folder_manifest_path(dir), key).map_err(anyhow::Error::msg)?;
     Ok(())
 }
+
+/// Export one standard-mode barcode to `path`, followed by its own manifest
+/// signed with `key`.
+pub fn export_image(
+    gray: &image::GrayImage,
+    config: &Config,
+    path: &std::path::Path,
+    export: &ImageExport,
+    key: &SiteKey,
+) -> Result<()> {
+    save_image(gray, config, path, export)?;
+    let mut manifest = Manifest::new(TOOL);
+    let label = ManifestLabel {
+        serial: String::new(),
+        content: config.content.clone(),
+    };
+    let params = image_params(format_name(config), config, export);
+    manifest
+        .add_file(path, vec![label], parameters(&params))
+        .map_err(anyhow::Error::msg)?;
+    write_manifest(manifest, &manifest_path_for(path), key).map_err(anyhow::Error::msg)
+}
+
+/// Write the barcodes as one ZPL file at `path` (see [`abbott_zpl`]),
+/// followed by its manifest signed with `key`.
+pub fn export_abbott_zpl(
+    items: &[AbbottBarcodeItem],
+    path: &std::path::Path,
+    key: &SiteKey,
+) -> Result<()> {
+    let Some(first) = items.first() else {
+        anyhow::bail!("没有可导出的条码");
+    };
+    fs::write(path, abbott_zpl(items))?;
+    let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+    let params = [
+        ("labels", labels.join("+")),
+        ("project", first.project.clone()),
+        ("lot", first.lot.clone()),
+        ("dpi", first.config.dpi.to_string()),
+        ("image_format", "zpl".to_string()),
+    ];
+    let labels = items
+        .iter()
+        .map(|item| ManifestLabel {
+            serial: item.serial.clone(),
+            content: item.content.clone(),
+        })
+        .collect();
+    let mut manifest = Manifest::new(TOOL);
+    manifest
+        .add_file(path, labels, parameters(&params))
+        .map_err(anyhow::Error::msg)?;
+    write_manifest(manifest, &manifest_path_for(path), key).map_err(anyhow::Error::msg)
+}
+
+/// Manifest parameters of an image of `label` generated with `cfg`.
+fn image_params(label: &str, cfg: &Config, export: &ImageExport) -> [(&'static str, String); 10] {
+    [
+        ("label", label.to_string()),
+        ("format_index", cfg.format_index.to_string()),
+        ("columns_index", cfg.columns_index.to_string()),
+        ("eclevel_index", cfg.eclevel_index.to_string()),
+        ("rotate_index", cfg.rotate_index.to_string()),
+        ("size_cm", format!("{}x{}", cfg.width_cm, cfg.height_cm)),
+        ("dpi", cfg.dpi.to_string()),
+        ("image_format", export.format_index.to_string()),
+        ("threshold", export.threshold.to_string()),
+        ("dither", export.dither.to_string()),
+    ]
+}
+
+/// Convert generated barcodes to ZPL, one label per barcode at its configured
+/// physical size. PDF417 / CompactPDF417 use the printer's native `^B7`; any
+/// other format is sent as a `^GF` graphic.
+pub fn abbott_zpl(items: &[AbbottBarcodeItem]) -> String {
+    use rust_learning::zpl::{self, Element, Orientation};
+
+    let labels: Vec<zpl::Label> = items
+        .iter()
+        .map(|item| {
+            let cfg = &item.config;
+            let dots = |cm: f32| (cm / 2.54 * cfg.dpi as f32).round() as u32;
+            let (mut width, mut height) = if cfg.width_cm > 0.0 && cfg.height_cm > 0.0 {
+                (dots(cfg.width_cm), dots(cfg.height_cm))
+            } else {
+                (item.gray_image.width(), item.gray_image.height())
+            };
+            let orientation = Orientation::from_quarter_turns(cfg.rotate_index);
+            let element = match cfg.format_index {
+                0 | 1 => {
+                    let compact = cfg.format_index == 0;
+                    let columns = cfg.columns_index as u32 + 1;
+                    // The symbol's width runs across the label, or down it when turned sideways.
+                    let span = if orientation.is_sideways() {
+                        height
+                    } else {
+                        width
+                    };
+                    let module = (span / zpl::pdf417_width_modules(columns, compact)).max(1);
+                    Element::Pdf417 {
+                        x: 0,
+                        y: 0,
+                        module,
+                        row_height: module * 3,
+                        columns,
+                        rows: 0,
+                        security: cfg.eclevel_index as u32,
+                        compact,
+                        orientation,
+                        data: item.content.clone(),
+                    }
+                }
+                _ => {
+                    // Whole-number scaling keeps every module the same number of dots;
+                    // the label grows if the symbol does not fit at 1×.
+                    let img = &item.gray_image;
+                    let factor = (width / img.width()).min(height / img.height()).max(1);
+                    let (w, h) = (img.width() * factor, img.height() * factor);
+                    width = width.max(w);
+                    height = height.max(h);
+                    Element::Graphic {
+                        x: (width - w) / 2,
+                        y: (height - h) / 2,
+                        image: image::imageops::resize(
+                            img,
+                            w,
+                            h,
+                            image::imageops::FilterType::Nearest,
+                        ),
+                    }
+                }
+            };
+            zpl::Label {
+                width,
+                height,
+                elements: vec![element],
+            }
+        })
+        .collect();
+    zpl::to_zpl(&labels, "0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::assert_golden;
+
+    fn item(config: Config) -> AbbottBarcodeItem {
+        // 30×12 pixels, 3×3 checkerboard blocks.
+        let gray_image = image::GrayImage::from_fn(30, 12, |x, y| {
+            image::Luma([if (x / 3 + y / 3) % 2 == 0 { 0 } else { 255 }])
+        });
+        AbbottBarcodeItem {
+            label: "A".into(),
+            project: "P".into(),
+            lot: "L".into(),
+            serial: "1".into(),
+            content: config.content.clone(),
+            slint_image: slint::Image::default(),
+            gray_image,
+            config,
+        }
+    }
+
+    #[test]
+    fn zpl_export_matches_golden() {
+        let mut items = vec![
+            item(long_config("A03157G71084UN2431122026", 300)),
+            item(short_config("A06975H91015UN24", 203)),
+        ];
+        for turns in [2, 3] {
+            let mut cfg = short_config("A06975H91015UN24", 203);
+            cfg.rotate_index = turns;
+            items.push(item(cfg));
+        }
+        // A QR code has no native equivalent: the 30×12 image fits 3× into the
+        // 100×40 dot label (1.25×0.5 cm at 203 DPI) and is centred.
+        let mut graphic = long_config("A03157G71084UN2431122026", 203);
+        graphic.format_index = 2;
+        graphic.width_cm = 1.25;
+        graphic.height_cm = 0.5;
+        items.push(item(graphic));
+        assert_golden("abbott.zpl", &abbott_zpl(&items));
+    }
+
+    #[test]
+    fn zpl_export_is_signed() {
+        let dir = std::env::temp_dir().join(format!("abbott-zpl-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let key = SiteKey::create(dir.join("site_key.json"), "secret").unwrap();
+        let items = [item(short_config("A06975H91015UN24", 203))];
+        let path = dir.join("abbott.zpl");
+        export_abbott_zpl(&items, &path, &key).unwrap();
+
+        let manifest = Manifest::load(&manifest_path_for(&path)).unwrap();
+        assert_eq!(manifest.verify_signature(&[key.public_key()]), Ok(()));
+        assert!(manifest.check_files(&dir, None).is_empty());
+        assert_eq!(manifest.files[0].labels[0].content, "A06975H91015UN24");
+        assert!(export_abbott_zpl(&[], &path, &key).is_err());
+    }
+}
@@ -1,34 +1,74 @@
-use crate::config::Config;
+use crate::config::{Config, ImageExport};
+use rust_learning::mono;
+use rust_learning::zpl;
+
+/// Upper bound on PDF417 data columns tried when fitting to a size.
+const MAX_PDF417_COLUMNS: usize = 30;
 
 pub struct BarcodeResult {
     pub gray_image: image::GrayImage,
     pub width: u32,
     pub height: u32,
     pub format_name: String,
+    /// Set when the symbol was sized with whole-dot modules.
+    pub fit: Option<ModuleFit>,
+}
+
+/// Outcome of fitting a symbol to the requested size with an integer module.
+pub struct ModuleFit {
+    /// Dots per module.
+    pub module: u32,
+    /// PDF417 data columns and rows; 0 for other formats.
+    pub columns: usize,
+    pub rows: u32,
+    /// Module width (X-dimension) in mm.
+    pub x_dim_mm: f32,
+    /// Actual minus requested width and height in mm.
+    pub error_mm: (f32, f32),
+}
+
+impl ModuleFit {
+    pub fn summary(&self) -> String {
+        let grid = if self.columns > 0 {
+            format!("{} 列 × {} 行 | ", self.columns, self.rows)
+        } else {
+            String::new()
+        };
+        format!(
+            "{}模块 {} 点，X = {:.3} mm | 尺寸误差 {:+.2} × {:+.2} mm",
+            grid, self.module, self.x_dim_mm, self.error_mm.0, self.error_mm.1
+        )
+    }
+}
+
+/// Barcode formats in the order of the format combo box.
+const FORMATS: [&str; 8] = [
+    "CompactPDF417",
+    "PDF417",
+    "QRCode",
+    "DataMatrix",
+    "Code128",
+    "Code39",
+    "Aztec",
+    "EAN13",
+];
+
+/// Format name for `config.format_index`.
+pub fn format_name(config: &Config) -> &'static str {
+    FORMATS
+        .get(config.format_index)
+        .copied()
+        .unwrap_or("CompactPDF417")
 }
 
 /// Generate barcode image without saving to disk.
 pub fn make_barcode_image(config: &Config) -> anyhow::Result<BarcodeResult> {
     use zxingcpp::*;
 
-    const FORMATS: [&str; 8] = [
-        "CompactPDF417",
-        "PDF417",
-        "QRCode",
-        "DataMatrix",
-        "Code128",
-        "Code39",
-        "Aztec",
-        "EAN13",
-    ];
     const SCALES: [i32; 5] = [1, 2, 3, 4, 5];
     const ROTATES: [i32; 4] = [0, 90, 180, 270];
 
-    let format_name = FORMATS
-        .get(config.format_index)
-        .copied()
-        .unwrap_or("CompactPDF417")
-        .to_string();
+    let format_name = format_name(config).to_string();
 
     let format = match format_name.as_str() {
         "CompactPDF417" => BarcodeFormat::CompactPDF417,
@@ -46,32 +86,94 @@ pub fn make_barcode_image(config: &Config) -> anyhow::Result<BarcodeResult> {
     let eclevel = config.eclevel_index;
     let scale = SCALES.get(config.scale_index).copied().unwrap_or(2);
     let rotate = ROTATES.get(config.rotate_index).copied().unwrap_or(0);
-    let options = format!("columns:{},eclevel:{}", columns, eclevel);
-
-    let barcode = create(format).options(&options).from_str(&config.content)?;
-
-    let img = barcode.to_image_with(
-        &write()
-            .scale(scale)
-            .add_quiet_zones(true)
-            .add_hrt(false)
-            .rotate(rotate),
-    )?;
-
-    let mut gray_image = image::GrayImage::from(&img);
-    // Scale to physical size (300 DPI)
-    if config.width_cm > 0.0 && config.height_cm > 0.0 {
-        let target_w = (config.width_cm / 2.54 * 300.0).round() as u32;
-        let target_h = (config.height_cm / 2.54 * 300.0).round() as u32;
-        if target_w > 0 && target_h > 0 {
-            gray_image = image::imageops::resize(
-                &gray_image,
+
+    let encode = |columns: usize| -> anyhow::Result<Barcode> {
+        let options = format!("columns:{},eclevel:{}", columns, eclevel);
+        Ok(create(format).options(&options).from_str(&config.content)?)
+    };
+    let render = |barcode: &Barcode, scale: i32, rotate: i32| -> anyhow::Result<image::GrayImage> {
+        let img = barcode.to_image_with(
+            &write()
+                .scale(scale)
+                .add_quiet_zones(true)
+                .add_hrt(false)
+                .rotate(rotate),
+        )?;
+        Ok(image::GrayImage::from(&img))
+    };
+
+    let dpi = config.dpi as f32;
+    let target = if config.width_cm > 0.0 && config.height_cm > 0.0 {
+        let target_w = (config.width_cm / 2.54 * dpi).round() as u32;
+        let target_h = (config.height_cm / 2.54 * dpi).round() as u32;
+        (target_w > 0 && target_h > 0).then_some((target_w, target_h))
+    } else {
+        None
+    };
+
+    let (gray_image, fit) = match target {
+        Some((target_w, target_h)) if config.exact_modules => {
+            // Search in the symbol's own orientation, then rotate the result.
+            let (box_w, box_h) = if rotate % 180 == 0 {
+                (target_w, target_h)
+            } else {
+                (target_h, target_w)
+            };
+            // Only PDF417 lets us trade columns for rows.
+            let candidates: Vec<usize> = if config.format_index <= 1 {
+                (1..=MAX_PDF417_COLUMNS).collect()
+            } else {
+                vec![columns]
+            };
+            // (error in dots, columns, module, unscaled symbol)
+            let mut best: Option<(u32, usize, u32, image::GrayImage)> = None;
+            for c in candidates {
+                // Too few columns can exceed the 90-row limit.
+                let Ok(barcode) = encode(c) else { continue };
+                let modules = render(&barcode, 1, 0)?;
+                let (w, h) = modules.dimensions();
+                let max_module = (box_w / w).max(box_h / h) + 1;
+                for m in 1..=max_module {
+                    let err = (w * m).abs_diff(box_w) + (h * m).abs_diff(box_h);
+                    if best.as_ref().is_none_or(|b| err < b.0) {
+                        best = Some((err, c, m, modules.clone()));
+                    }
+                }
+            }
+            let Some((_, c, m, modules)) = best else {
+                anyhow::bail!("内容无法编码为 {}", format_name);
+            };
+            let gray_image = render(&encode(c)?, m as i32, rotate)?;
+            let mm = |dots: u32| dots as f32 / dpi * 25.4;
+            let fit = ModuleFit {
+                module: m,
+                columns: if config.format_index <= 1 { c } else { 0 },
+                rows: if config.format_index <= 1 {
+                    zpl::pdf417_rows(&modules)
+                } else {
+                    0
+                },
+                x_dim_mm: mm(m),
+                error_mm: (
+                    mm(gray_image.width()) - config.width_cm * 10.0,
+                    mm(gray_image.height()) - config.height_cm * 10.0,
+                ),
+            };
+            (gray_image, Some(fit))
+        }
+        Some((target_w, target_h)) => {
+            let img = render(&encode(columns)?, scale, rotate)?;
+            let gray_image = image::imageops::resize(
+                &img,
                 target_w,
                 target_h,
                 image::imageops::FilterType::Nearest,
             );
+            (gray_image, None)
         }
-    }
+        None => (render(&encode(columns)?, scale, rotate)?, None),
+    };
+    verify_scan(&gray_image, &config.content, format_name == "EAN13")?;
     let width = gray_image.width();
     let height = gray_image.height();
 
@@ -80,13 +182,33 @@ pub fn make_barcode_image(config: &Config) -> anyhow::Result<BarcodeResult> {
         width,
         height,
         format_name,
+        fit,
     })
 }
 
+/// Decode `gray` with the zxing reader and check it yields `content` byte for
+/// byte. EAN-13 content may omit the check digit, which the encoder appends.
+fn verify_scan(gray: &image::GrayImage, content: &str, ean13: bool) -> anyhow::Result<()> {
+    let found = zxingcpp::read().try_harder(true).from(gray)?;
+    let Some(barcode) = found.first() else {
+        anyhow::bail!("扫码校验失败: 图像中未识别到条码，请调整尺寸或缩放后重试");
+    };
+    let bytes = barcode.bytes();
+    let matches = bytes == content.as_bytes()
+        || (ean13 && bytes.len() == content.len() + 1 && bytes.starts_with(content.as_bytes()));
+    if !matches {
+        anyhow::bail!(
+            "扫码校验失败: 识别内容与源内容不一致\n源内容: {content}\n识别结果: {}",
+            barcode.text()
+        );
+    }
+    Ok(())
+}
+
 /// Generate barcode and save to out.png.
 pub fn generate_barcode(config: &Config) -> anyhow::Result<BarcodeResult> {
     let result = make_barcode_image(config)?;
-    save_png_300dpi(&result.gray_image, "out.png")?;
+    save_png(&result.gray_image, "out.png", config.dpi)?;
     Ok(result)
 }
 
@@ -102,12 +224,34 @@ pub fn gray_to_slint_image(gray: &image::GrayImage) -> slint::Image {
     slint::Image::from_rgba8(buffer)
 }
 
-/// Save grayscale PNG with 300 DPI metadata (pHYs chunk: 11811 px/m).
-pub fn save_png_300dpi(
+/// Save as 8-bit PNG, or as 1-bit PNG / BMP when `export` asks for it. The
+/// thresholded image is scanned back against `config.content` before it is
+/// written, since thresholding or dithering can break a symbol.
+pub fn save_image(
+    gray: &image::GrayImage,
+    config: &Config,
+    path: impl AsRef<std::path::Path>,
+    export: &ImageExport,
+) -> anyhow::Result<()> {
+    match export.mono_format() {
+        None => save_png(gray, path, config.dpi),
+        Some(format) => {
+            let opts = export.mono_options();
+            let mono = mono::to_mono(gray, &opts);
+            verify_scan(&mono, &config.content, format_name(config) == "EAN13")
+                .map_err(|e| anyhow::anyhow!("1位图像{e}"))?;
+            mono::save_mono(&mono, path, config.dpi, format, &opts).map_err(anyhow::Error::msg)
+        }
+    }
+}
+
+/// Save grayscale PNG with `dpi` in its pHYs chunk (300 DPI is 11811 px/m).
+pub fn save_png(
     gray: &image::GrayImage,
     path: impl AsRef<std::path::Path>,
+    dpi: u32,
 ) -> anyhow::Result<()> {
-    const PIXELS_PER_METER: u32 = 11811;
+    let pixels_per_meter = (dpi as f32 / 0.0254).round() as u32;
 
     let file = std::fs::File::create(path)?;
     let buf = std::io::BufWriter::new(file);
@@ -116,8 +260,8 @@ pub fn save_png_300dpi(
     encoder.set_color(png::ColorType::Grayscale);
     encoder.set_depth(png::BitDepth::Eight);
     encoder.set_pixel_dims(Some(png::PixelDimensions {
-        xppu: PIXELS_PER_METER,
-        yppu: PIXELS_PER_METER,
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
         unit: png::Unit::Meter,
     }));
 
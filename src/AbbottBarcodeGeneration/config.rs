@@ -1,3 +1,4 @@
+use rust_learning::mono::{MonoFormat, MonoOptions, mono_format};
 use std::fs;
 use std::path::PathBuf;
 
@@ -7,8 +8,11 @@ fn default_width_cm() -> f32 {
 fn default_height_cm() -> f32 {
     2.0
 }
+fn default_dpi() -> u32 {
+    300
+}
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct Config {
     pub content: String,
     pub format_index: usize,
@@ -20,6 +24,14 @@ pub struct Config {
     pub width_cm: f32,
     #[serde(default = "default_height_cm")]
     pub height_cm: f32,
+    /// Printer resolution physical sizes are converted at.
+    #[serde(default = "default_dpi")]
+    pub dpi: u32,
+    /// Size with a whole number of dots per module instead of stretching.
+    #[serde(default)]
+    pub exact_modules: bool,
+    #[serde(default)]
+    pub export: ImageExport,
     #[serde(default)]
     pub abbott_mode: bool,
     #[serde(default)]
@@ -37,12 +49,53 @@ impl Default for Config {
             eclevel_index: 2, // 纠错等级 2（PDF417 默认）
             width_cm: 0.0,
             height_cm: 0.0,
+            dpi: default_dpi(),
+            exact_modules: false,
+            export: ImageExport::default(),
             abbott_mode: false,
             abbott_project_index: 0,
         }
     }
 }
 
+/// Image file format for exports.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ImageExport {
+    /// Index into `mono::EXPORT_FORMATS`: 8-bit PNG, 1-bit PNG or 1-bit BMP.
+    pub format_index: usize,
+    pub threshold: u8,
+    pub dither: bool,
+}
+
+impl Default for ImageExport {
+    fn default() -> Self {
+        let mono = MonoOptions::default();
+        ImageExport {
+            format_index: 0,
+            threshold: mono.threshold,
+            dither: mono.dither,
+        }
+    }
+}
+
+impl ImageExport {
+    /// `None` for 8-bit grayscale.
+    pub fn mono_format(&self) -> Option<MonoFormat> {
+        mono_format(self.format_index)
+    }
+
+    pub fn mono_options(&self) -> MonoOptions {
+        MonoOptions {
+            threshold: self.threshold,
+            dither: self.dither,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.mono_format().map_or("png", MonoFormat::extension)
+    }
+}
+
 fn config_path() -> PathBuf {
     PathBuf::from("./assets/barcode_config.json")
 }
@@ -2,19 +2,29 @@
 mod abbott;
 mod barcode;
 mod config;
+#[cfg(test)]
+#[path = "../../tests/support/golden.rs"]
+mod golden;
 
 use abbott::{
-    AbbottBarcodeItem, AbbottProjectsConfig, export_abbott_barcodes, generate_abbott_barcodes,
-    load_abbott_projects,
+    AbbottBarcodeItem, AbbottProjectsConfig, export_abbott_barcodes, export_abbott_zpl,
+    export_image, generate_abbott_barcodes, load_abbott_projects, record_export, regenerate,
 };
-use barcode::{generate_barcode, gray_to_slint_image, save_png_300dpi};
-use config::{AuthConfig, Config, clear_auth_config, load_auth_config, load_config, save_auth_config, save_config};
+use barcode::{format_name, generate_barcode, gray_to_slint_image};
+use config::{AuthConfig, Config, ImageExport, clear_auth_config, load_auth_config, load_config, save_auth_config, save_config};
 use rfd::FileDialog;
+use rust_learning::audit::{AuditLog, AuditRecord};
+use rust_learning::manifest::{self, SITE_KEY_PATH, SiteKey, TRUSTED_KEYS_PATH};
+use rust_learning::mono::EXPORT_FORMATS;
+use rust_learning::zpl::PRINTER_DPIS;
 use slint::{ModelRc, VecModel};
 use std::sync::{Arc, Mutex};
 
 slint::include_modules!();
 
+/// Site key that signs export manifests, once unlocked or created.
+type SiteKeySlot = Arc<Mutex<Option<SiteKey>>>;
+
 fn restore_config(window: &BarcodeWindow, cfg: &Config) {
     window.set_content(cfg.content.clone().into());
     window.set_format_index(cfg.format_index as i32);
@@ -24,13 +34,42 @@ fn restore_config(window: &BarcodeWindow, cfg: &Config) {
     window.set_eclevel_index(cfg.eclevel_index as i32);
     window.set_width_cm(format!("{}", cfg.width_cm).into());
     window.set_height_cm(format!("{}", cfg.height_cm).into());
+    window.set_exact_modules(cfg.exact_modules);
+    window.set_dpi_index(PRINTER_DPIS.iter().position(|d| *d == cfg.dpi).unwrap_or(1) as i32);
+    let formats: Vec<slint::SharedString> = EXPORT_FORMATS.iter().map(|s| (*s).into()).collect();
+    window.set_export_formats(ModelRc::new(VecModel::from(formats)));
+    window.set_export_format_index(cfg.export.format_index as i32);
+    window.set_mono_threshold(cfg.export.threshold.to_string().into());
+    window.set_mono_dither(cfg.export.dither);
     window.set_abbott_mode(cfg.abbott_mode);
     window.set_abbott_project_index(cfg.abbott_project_index as i32);
 }
 
+/// Printer resolution chosen in the DPI combo box.
+fn selected_dpi(window: &BarcodeWindow) -> u32 {
+    PRINTER_DPIS
+        .get(window.get_dpi_index() as usize)
+        .copied()
+        .unwrap_or(300)
+}
+
+/// Image format, threshold and dithering chosen for exports.
+fn image_export(window: &BarcodeWindow) -> Result<ImageExport, String> {
+    let threshold = window
+        .get_mono_threshold()
+        .trim()
+        .parse::<u8>()
+        .map_err(|_| "二值化阈值必须为 0-255 的整数".to_string())?;
+    Ok(ImageExport {
+        format_index: window.get_export_format_index().max(0) as usize,
+        threshold,
+        dither: window.get_mono_dither(),
+    })
+}
+
 fn setup_generate_callback(
     window: &BarcodeWindow,
-    last_gray: Arc<Mutex<Option<image::GrayImage>>>,
+    last_gray: Arc<Mutex<Option<(image::GrayImage, Config)>>>,
 ) {
     let window_weak = window.as_weak();
     window.on_generate(move || {
@@ -44,23 +83,33 @@ fn setup_generate_callback(
             eclevel_index: window.get_eclevel_index() as usize,
             width_cm: window.get_width_cm().parse::<f32>().unwrap_or(0.0),
             height_cm: window.get_height_cm().parse::<f32>().unwrap_or(0.0),
+            dpi: selected_dpi(&window),
+            exact_modules: window.get_exact_modules(),
+            export: image_export(&window).unwrap_or_default(),
             abbott_mode: window.get_abbott_mode(),
             abbott_project_index: window.get_abbott_project_index() as usize,
         };
         match generate_barcode(&config) {
             Ok(result) => {
                 save_config(&config);
-                let msg = format!(
+                let mut msg = format!(
                     "{} | {}x{} px | out.png",
                     result.format_name, result.width, result.height
                 );
+                if let Some(fit) = &result.fit {
+                    msg.push_str(" | ");
+                    msg.push_str(&fit.summary());
+                }
                 let slint_img = gray_to_slint_image(&result.gray_image);
-                *last_gray.lock().unwrap() = Some(result.gray_image);
+                *last_gray.lock().unwrap() = Some((result.gray_image, config.clone()));
                 window.set_preview(slint_img);
                 window.set_has_preview(true);
                 window.set_status(msg.into());
             }
             Err(e) => {
+                // 不保留上一次的图像，避免校验失败后仍导出旧条码
+                *last_gray.lock().unwrap() = None;
+                window.set_has_preview(false);
                 window.set_status(format!("错误: {}", e).into());
             }
         }
@@ -69,13 +118,13 @@ fn setup_generate_callback(
 
 fn setup_clipboard_callback(
     window: &BarcodeWindow,
-    last_gray: Arc<Mutex<Option<image::GrayImage>>>,
+    last_gray: Arc<Mutex<Option<(image::GrayImage, Config)>>>,
 ) {
     let window_weak = window.as_weak();
     window.on_copy_to_clipboard(move || {
         let window = window_weak.unwrap();
         let guard = last_gray.lock().unwrap();
-        if let Some(gray) = guard.as_ref() {
+        if let Some((gray, _)) = guard.as_ref() {
             let w = gray.width() as usize;
             let h = gray.height() as usize;
             let rgba: Vec<u8> = gray
@@ -102,23 +151,52 @@ fn setup_clipboard_callback(
     });
 }
 
+/// Audit a standard-mode barcode once its image has been written.
+fn record_image_export(config: &Config) -> anyhow::Result<()> {
+    let record = AuditRecord {
+        plaintext: config.content.clone(),
+        content: config.content.clone(),
+        ..AuditRecord::now(abbott::TOOL, format_name(config))
+    };
+    AuditLog::default()
+        .append(&[record])
+        .map_err(anyhow::Error::msg)
+}
+
 fn setup_export_image_callback(
     window: &BarcodeWindow,
-    last_gray: Arc<Mutex<Option<image::GrayImage>>>,
+    last_gray: Arc<Mutex<Option<(image::GrayImage, Config)>>>,
+    site_key: SiteKeySlot,
 ) {
     let window_weak = window.as_weak();
     window.on_export_image(move || {
         let window = window_weak.unwrap();
         let guard = last_gray.lock().unwrap();
+        let export = match image_export(&window) {
+            Ok(export) => export,
+            Err(e) => {
+                window.set_toast_message(e.into());
+                window.set_toast_visible(true);
+                return;
+            }
+        };
+        let ext = export.extension();
         let now = chrono::Local::now();
-        let filename = format!("barcode_{}.png", now.format("%Y%m%d_%H%M%S"));
-        if let Some(gray) = guard.as_ref() {
+        let filename = format!("barcode_{}.{}", now.format("%Y%m%d_%H%M%S"), ext);
+        if let Some((gray, config)) = guard.as_ref() {
+            let key = site_key.lock().unwrap();
+            let Some(key) = key.as_ref() else {
+                ask_site_key(&window);
+                return;
+            };
             let msg = match FileDialog::new()
-                .add_filter("PNG Image", &["png"])
+                .add_filter(format!("{} Image", ext.to_uppercase()), &[ext])
                 .set_file_name(filename)
                 .save_file()
             {
-                Some(path) => match save_png_300dpi(gray, &path) {
+                Some(path) => match export_image(gray, config, &path, &export, key)
+                    .and_then(|_| record_image_export(config))
+                {
                     Ok(_) => format!("导出成功: {}", path.display()),
                     Err(e) => format!("导出失败: {}", e),
                 },
@@ -163,6 +241,7 @@ fn setup_abbott_callbacks(
     window: &BarcodeWindow,
     projects_cfg: Arc<AbbottProjectsConfig>,
     last_abbott: Arc<Mutex<Vec<AbbottBarcodeItem>>>,
+    site_key: SiteKeySlot,
 ) {
     // Project changed → update reagent count, project bits, defaults
     {
@@ -210,7 +289,9 @@ fn setup_abbott_callbacks(
             let expiry = window.get_abbott_expiry().to_string();
             let project_bits = window.get_abbott_project_bits().to_string();
 
-            match generate_abbott_barcodes(project, &sns, &control_no, &expiry, &project_bits) {
+            let dpi = selected_dpi(&window);
+            match generate_abbott_barcodes(project, &sns, &control_no, &expiry, &project_bits, dpi)
+            {
                 Ok(items) => {
                     let labels: Vec<slint::SharedString> =
                         items.iter().map(|it| it.label.clone().into()).collect();
@@ -228,6 +309,7 @@ fn setup_abbott_callbacks(
                     window.set_status(format!("已生成 {} 个条码", count).into());
                 }
                 Err(e) => {
+                    last.lock().unwrap().clear();
                     window.set_status(format!("生成失败: {}", e).into());
                 }
             }
@@ -257,6 +339,42 @@ fn setup_abbott_callbacks(
         });
     }
 
+    // Export all Abbott barcodes as one ZPL file
+    {
+        let window_weak = window.as_weak();
+        let last = last_abbott.clone();
+        let site_key = site_key.clone();
+        window.on_abbott_export_zpl(move || {
+            let window = window_weak.unwrap();
+            let items = last.lock().unwrap();
+            if items.is_empty() {
+                window.set_toast_message("没有可导出的条码".into());
+                window.set_toast_visible(true);
+                return;
+            }
+            let key = site_key.lock().unwrap();
+            let Some(key) = key.as_ref() else {
+                ask_site_key(&window);
+                return;
+            };
+            let now = chrono::Local::now();
+            if let Some(path) = FileDialog::new()
+                .add_filter("ZPL", &["zpl"])
+                .set_file_name(format!("abbott_{}.zpl", now.format("%Y%m%d_%H%M%S")))
+                .save_file()
+            {
+                let written =
+                    export_abbott_zpl(&items, &path, key).and_then(|_| record_export(&items));
+                let msg = match written {
+                    Ok(_) => format!("已导出 {} 个条码到: {}", items.len(), path.display()),
+                    Err(e) => format!("导出失败: {}", e),
+                };
+                window.set_toast_message(msg.into());
+                window.set_toast_visible(true);
+            }
+        });
+    }
+
     // Export all Abbott barcodes to a folder
     {
         let window_weak = window.as_weak();
@@ -268,8 +386,23 @@ fn setup_abbott_callbacks(
                 window.set_toast_visible(true);
                 return;
             }
+            let export = match image_export(&window) {
+                Ok(export) => export,
+                Err(e) => {
+                    window.set_toast_message(e.into());
+                    window.set_toast_visible(true);
+                    return;
+                }
+            };
+            let key = site_key.lock().unwrap();
+            let Some(key) = key.as_ref() else {
+                ask_site_key(&window);
+                return;
+            };
             if let Some(dir) = FileDialog::new().pick_folder() {
-                let msg = match export_abbott_barcodes(&items, &dir) {
+                let exported = export_abbott_barcodes(&items, &dir, &export, key)
+                    .and_then(|_| record_export(&items));
+                let msg = match exported {
                     Ok(_) => format!("已导出 {} 个文件到: {}", items.len(), dir.display()),
                     Err(e) => format!("导出失败: {}", e),
                 };
@@ -280,6 +413,132 @@ fn setup_abbott_callbacks(
     }
 }
 
+/// Show audit search results in the history dialog.
+fn show_history(window: &BarcodeWindow, records: &[AuditRecord]) {
+    let column = |f: fn(&AuditRecord) -> &str| -> ModelRc<slint::SharedString> {
+        let v: Vec<slint::SharedString> = records.iter().map(|r| f(r).into()).collect();
+        ModelRc::new(VecModel::from(v))
+    };
+    window.set_history_times(column(|r| r.timestamp.as_str()));
+    window.set_history_labels(column(|r| r.label_type.as_str()));
+    window.set_history_lots(column(|r| r.lot.as_str()));
+    window.set_history_serials(column(|r| r.serial.as_str()));
+    window.set_history_contents(column(|r| r.content.as_str()));
+}
+
+fn setup_history_callbacks(window: &BarcodeWindow, site_key: SiteKeySlot) {
+    let history: Arc<Mutex<Vec<AuditRecord>>> = Arc::default();
+    {
+        let window_weak = window.as_weak();
+        window.on_history_verify(move || {
+            let window = window_weak.unwrap();
+            let msg = match AuditLog::default().verify() {
+                Ok(count) => format!("审计记录完整，共 {} 条", count),
+                Err(e) => format!("审计记录校验失败: {}", e),
+            };
+            window.set_history_status(msg.into());
+        });
+    }
+    {
+        let window_weak = window.as_weak();
+        let history = history.clone();
+        window.on_search_history(move |query| {
+            let window = window_weak.unwrap();
+            match AuditLog::default().search(&query) {
+                Ok(records) => {
+                    show_history(&window, &records);
+                    window.set_history_status(format!("找到 {} 条记录", records.len()).into());
+                    *history.lock().unwrap() = records;
+                }
+                Err(e) => window.set_history_status(e.into()),
+            }
+        });
+    }
+
+    // Regenerate the Abbott project barcodes among the results and export them;
+    // standard-mode records carry no project and are skipped
+    {
+        let window_weak = window.as_weak();
+        window.on_history_reexport(move || {
+            let window = window_weak.unwrap();
+            let export = match image_export(&window) {
+                Ok(export) => export,
+                Err(e) => {
+                    window.set_history_status(e.into());
+                    return;
+                }
+            };
+            let dpi = selected_dpi(&window);
+            let items = history
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|r| r.tool == abbott::TOOL && !r.project.is_empty())
+                .map(|r| regenerate(r, dpi))
+                .collect::<anyhow::Result<Vec<_>>>();
+            let items = match items {
+                Ok(items) if items.is_empty() => {
+                    window.set_history_status("没有可重新导出的雅培条码".into());
+                    return;
+                }
+                Ok(items) => items,
+                Err(e) => {
+                    window.set_history_status(format!("重新生成失败: {}", e).into());
+                    return;
+                }
+            };
+            let key = site_key.lock().unwrap();
+            let Some(key) = key.as_ref() else {
+                ask_site_key(&window);
+                return;
+            };
+            if let Some(dir) = FileDialog::new().pick_folder() {
+                let msg = match export_abbott_barcodes(&items, &dir, &export, key) {
+                    Ok(_) => format!("已重新导出 {} 个文件到: {}", items.len(), dir.display()),
+                    Err(e) => format!("导出失败: {}", e),
+                };
+                window.set_history_status(msg.into());
+            }
+        });
+    }
+}
+
+/// Exports are signed: open the key dialog while the site key is locked.
+fn ask_site_key(window: &BarcodeWindow) {
+    window.set_site_key_create(!std::path::Path::new(SITE_KEY_PATH).exists());
+    window.set_site_key_error("导出前请先解锁站点签名密钥".into());
+    window.set_site_key_dialog_visible(true);
+}
+
+/// Unlock the site key, or create it when the dialog was opened for that.
+fn setup_site_key_callback(window: &BarcodeWindow, site_key: SiteKeySlot) {
+    let window_weak = window.as_weak();
+    window.on_site_key_submit(move |passphrase| {
+        let window = window_weak.unwrap();
+        let create = window.get_site_key_create();
+        let result = if create {
+            SiteKey::create(SITE_KEY_PATH, &passphrase)
+        } else {
+            SiteKey::load(SITE_KEY_PATH, &passphrase)
+        };
+        match result {
+            Ok(key) => {
+                *site_key.lock().unwrap() = Some(key);
+                window.set_site_key_dialog_visible(false);
+                window.set_site_key_error("".into());
+                let msg = if create {
+                    format!("已创建签名密钥，请把 {} 分发给校验方", TRUSTED_KEYS_PATH)
+                } else {
+                    "签名密钥已解锁".to_string()
+                };
+                window.set_toast_message(msg.into());
+                window.set_toast_visible(true);
+            }
+            Err(e) => window.set_site_key_error(e.into()),
+        }
+    });
+}
+
 fn setup_menu_callbacks(window: &BarcodeWindow, projects_cfg: Arc<AbbottProjectsConfig>) {
     window.on_quit(|| {
         slint::quit_event_loop().unwrap();
@@ -307,6 +566,29 @@ fn setup_menu_callbacks(window: &BarcodeWindow, projects_cfg: Arc<AbbottProjects
             .show();
     });
 
+    window.on_verify_manifest(move || {
+        let Some(path) = FileDialog::new()
+            .set_title("选择导出清单")
+            .add_filter("清单文件", &["json"])
+            .pick_file()
+        else {
+            return;
+        };
+        let (level, msg) = match manifest::verify_manifest(&path) {
+            Ok(count) => (
+                rfd::MessageLevel::Info,
+                format!("清单签名有效，{} 个文件全部一致", count),
+            ),
+            Err(e) => (rfd::MessageLevel::Error, format!("清单校验失败:\n{}", e)),
+        };
+        rfd::MessageDialog::new()
+            .set_title("校验导出清单")
+            .set_level(level)
+            .set_description(msg)
+            .set_buttons(rfd::MessageButtons::Ok)
+            .show();
+    });
+
     // Toggle Abbott mode from menu (called only when turning OFF)
     {
         let window_weak = window.as_weak();
@@ -385,14 +667,17 @@ fn main() {
         window.set_auth_saved_password(auth_cfg.password.clone().into());
     }
 
-    let last_gray: Arc<Mutex<Option<image::GrayImage>>> = Arc::new(Mutex::new(None));
+    let last_gray: Arc<Mutex<Option<(image::GrayImage, Config)>>> = Arc::new(Mutex::new(None));
     let last_abbott: Arc<Mutex<Vec<AbbottBarcodeItem>>> = Arc::new(Mutex::new(Vec::new()));
 
     setup_generate_callback(&window, last_gray.clone());
     setup_clipboard_callback(&window, last_gray.clone());
-    setup_export_image_callback(&window, last_gray.clone());
-    setup_abbott_callbacks(&window, projects_cfg.clone(), last_abbott);
+    let site_key: SiteKeySlot = Arc::default();
+    setup_export_image_callback(&window, last_gray.clone(), site_key.clone());
+    setup_abbott_callbacks(&window, projects_cfg.clone(), last_abbott, site_key.clone());
     setup_menu_callbacks(&window, projects_cfg.clone());
+    setup_history_callbacks(&window, site_key.clone());
+    setup_site_key_callback(&window, site_key);
 
     window.run().unwrap();
 }
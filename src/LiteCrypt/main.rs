@@ -3,13 +3,58 @@
 mod crypto;
 mod excel;
 
+use rust_learning::audit::{AuditLog, AuditRecord};
+use std::cell::RefCell;
+use std::rc::Rc;
+
 slint::include_modules!();
 
+/// Append one audit record per exported cell. `plaintext` and `content` are the
+/// cell and the input line it was computed from, so both columns are recorded
+/// against their own source rather than against each other.
+fn record_export(
+    path: &std::path::Path,
+    encrypted: (&str, &str),
+    decrypted: (&str, &str),
+) -> Result<(), String> {
+    let file = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let record = |label_type: &str, i: usize, plaintext: &str, content: &str| AuditRecord {
+        file: file.clone(),
+        // Row 1 is the header.
+        row: Some(i as u32 + 2),
+        plaintext: plaintext.to_string(),
+        content: content.to_string(),
+        ..AuditRecord::now("LiteCrypt", label_type)
+    };
+    let (source, result) = encrypted;
+    let mut records: Vec<AuditRecord> = source
+        .lines()
+        .zip(result.lines())
+        .enumerate()
+        .map(|(i, (plain, enc))| record("加密结果", i, plain, enc))
+        .collect();
+    let (source, result) = decrypted;
+    records.extend(
+        source
+            .lines()
+            .zip(result.lines())
+            .enumerate()
+            .map(|(i, (enc, plain))| record("解密结果", i, plain, enc)),
+    );
+    AuditLog::default().append(&records)
+}
+
 fn main() {
     let window = LiteCryptWindow::new().unwrap();
+    // Input lines behind the current encrypted / decrypted results.
+    let sources = Rc::new(RefCell::new((String::new(), String::new())));
 
     {
         let w = window.as_weak();
+        let sources = sources.clone();
         window.on_encrypt(move || {
             let window = w.unwrap();
             let input = window.get_input_text().to_string();
@@ -18,6 +63,7 @@ fn main() {
             match crypto::encrypt_lines(&input, &key) {
                 Ok(result) => {
                     window.set_encrypted_result(result.clone().into());
+                    sources.borrow_mut().0 = input;
                     window.set_status("加密成功".into());
                 }
                 Err(e) => {
@@ -29,6 +75,7 @@ fn main() {
 
     {
         let w = window.as_weak();
+        let sources = sources.clone();
         window.on_decrypt(move || {
             let window = w.unwrap();
             let input = window.get_input_text().to_string();
@@ -37,6 +84,7 @@ fn main() {
             match crypto::decrypt_lines(&input, &key) {
                 Ok(result) => {
                     window.set_decrypted_result(result.clone().into());
+                    sources.borrow_mut().1 = input;
                     window.set_status("解密成功".into());
                 }
                 Err(e) => {
@@ -48,6 +96,7 @@ fn main() {
 
     {
         let w = window.as_weak();
+        let sources = sources.clone();
         window.on_save_results(move || {
             let window = w.unwrap();
             let encrypted = window.get_encrypted_result().to_string();
@@ -69,7 +118,17 @@ fn main() {
                         Ok(_) => {
                             window.set_encrypted_result("".into());
                             window.set_decrypted_result("".into());
-                            window.set_status(format!("已保存到: {}", p.display()).into());
+                            let (enc_src, dec_src) = sources.take();
+                            match record_export(&p, (&enc_src, &encrypted), (&dec_src, &decrypted))
+                            {
+                                Ok(_) => {
+                                    window.set_status(format!("已保存到: {}", p.display()).into())
+                                }
+                                Err(e) => window.set_status(
+                                    format!("已保存到: {}，但审计记录写入失败: {e}", p.display())
+                                        .into(),
+                                ),
+                            }
                         }
                         Err(e) => {
                             window.set_status(format!("保存失败: {e}").into());
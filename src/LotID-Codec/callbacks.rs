@@ -1,11 +1,15 @@
 use crate::codec::{decode, encode};
 use crate::imaging::{copy_gray_to_clipboard, gray_to_slint, make_datamatrix};
 use crate::LotIdWindow;
-use slint::ComponentHandle;
+use rust_learning::mono::{mono_format, save_mono, MonoOptions, EXPORT_FORMATS};
+use slint::{ComponentHandle, ModelRc, VecModel};
 use std::sync::{Arc, Mutex};
 
 type LastGray = Arc<Mutex<Option<image::GrayImage>>>;
 
+/// Resolution recorded in 1-bit files; the DataMatrix itself is resolution-free.
+const SAVE_DPI: u32 = 300;
+
 pub fn setup_callbacks(window: &LotIdWindow, last_gray: LastGray) {
     setup_encode_callback(window, last_gray.clone());
     setup_decode_callback(window);
@@ -127,26 +131,45 @@ fn setup_copy_image_callback(window: &LotIdWindow, last_gray: LastGray) {
 }
 
 fn setup_save_image_callback(window: &LotIdWindow, last_gray: LastGray) {
+    let formats: Vec<slint::SharedString> = EXPORT_FORMATS.iter().map(|s| (*s).into()).collect();
+    window.set_export_formats(ModelRc::new(VecModel::from(formats)));
+
     let window_weak = window.as_weak();
     window.on_save_image(move || {
         let window = window_weak.unwrap();
         let guard = last_gray.lock().unwrap();
         if let Some(gray) = guard.as_ref() {
+            let format = mono_format(window.get_export_format_index().max(0) as usize);
+            let Ok(threshold) = window.get_mono_threshold().trim().parse::<u8>() else {
+                window.set_status("二值化阈值必须为 0-255 的整数".into());
+                return;
+            };
+            let opts = MonoOptions {
+                threshold,
+                dither: window.get_mono_dither(),
+            };
+            let ext = format.map_or("png", |f| f.extension());
             let code = window.get_code_result().to_string();
             let filename = if code.is_empty() {
-                "lotid.png".to_string()
+                format!("lotid.{ext}")
             } else {
-                format!("{}.png", code)
+                format!("{code}.{ext}")
             };
             let msg = match rfd::FileDialog::new()
-                .add_filter("PNG Image", &["png"])
+                .add_filter(format!("{} Image", ext.to_uppercase()), &[ext])
                 .set_file_name(filename)
                 .save_file()
             {
-                Some(path) => match gray.save(&path) {
-                    Ok(_) => format!("已保存: {}", path.display()),
-                    Err(e) => format!("保存失败: {e}"),
-                },
+                Some(path) => {
+                    let saved = match format {
+                        None => gray.save(&path).map_err(|e| e.to_string()),
+                        Some(f) => save_mono(gray, &path, SAVE_DPI, f, &opts),
+                    };
+                    match saved {
+                        Ok(_) => format!("已保存: {}", path.display()),
+                        Err(e) => format!("保存失败: {e}"),
+                    }
+                }
                 None => return,
             };
             window.set_status(msg.into());
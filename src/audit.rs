@@ -0,0 +1,388 @@
+//! 标签生成审计记录
+//!
+//! 每生成一张标签就追加一行 JSON 到 `Setting/audit.jsonl`，各工具共用同一个文件，
+//! 召回调查时可以按批号、序列号等检索"某批到底打印过哪些标签"。文件只追加不改写，
+//! 单行损坏不影响其余记录的读取。
+//!
+//! 记录组成哈希链：每条记录带上前一条的 SHA-256，自身哈希覆盖全部字段。旁边的
+//! `audit.head` 保存记录数和最后一条的哈希，[`AuditLog::verify`] 据此发现修改、
+//! 删除（包括截掉末尾）和调换顺序。哈希链不带密钥，能写这两个文件的人可以重算
+//! 整条链，所以它只能发现意外损坏和不知情的手工改动，防不了蓄意篡改；需要时应把
+//! 文件放在只追加的存储上，或定期把链尾哈希另行保存。
+//!
+//! 追加时持有 `audit.lock` 的排他锁，多个工具同时写入也不会分叉。先把记录刷到
+//! 磁盘再替换链尾，两步之间崩溃只会让链尾落后；多出的记录正好接在链尾之后时，
+//! 校验和下一次追加都会把它们补进链尾，不会误报篡改。
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// 默认审计文件位置
+pub const AUDIT_PATH: &str = "Setting/audit.jsonl";
+
+/// 一张标签的生成记录
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// 本地时间 `YYYY-MM-DD HH:MM:SS`
+    pub timestamp: String,
+    pub operator: String,
+    /// 生成工具，如 `RL-CLIA`
+    pub tool: String,
+    pub label_type: String,
+    pub project: String,
+    pub lot: String,
+    pub serial: String,
+    /// 加密前的明文，无加密的条码与 `content` 相同
+    pub plaintext: String,
+    /// 条码实际编码的内容
+    pub content: String,
+    /// 导出的文件名，只用于按文件导出的记录
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub file: String,
+    /// 记录在导出表格中的行号
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row: Option<u32>,
+    /// 前一条记录的哈希，第一条为空
+    #[serde(default)]
+    pub prev_hash: String,
+    /// 本条记录（不含此字段）的 SHA-256，十六进制
+    #[serde(default)]
+    pub hash: String,
+}
+
+/// 链尾：记录数与最后一条的哈希
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct Head {
+    records: usize,
+    hash: String,
+}
+
+impl AuditRecord {
+    /// 以当前时间和操作员创建记录
+    pub fn now(tool: &str, label_type: &str) -> AuditRecord {
+        AuditRecord {
+            timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            operator: operator(),
+            tool: tool.to_string(),
+            label_type: label_type.to_string(),
+            project: String::new(),
+            lot: String::new(),
+            serial: String::new(),
+            plaintext: String::new(),
+            content: String::new(),
+            file: String::new(),
+            row: None,
+            prev_hash: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    /// 除 `hash` 外全部字段的 SHA-256
+    fn digest(&self) -> String {
+        let unhashed = AuditRecord {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let json = serde_json::to_string(&unhashed).unwrap_or_default();
+        format!("{:x}", Sha256::digest(json.as_bytes()))
+    }
+
+    /// 每个搜索词（空白分隔，不区分大小写）都要出现在某个字段中
+    pub fn matches(&self, query: &str) -> bool {
+        let fields = [
+            &self.timestamp,
+            &self.operator,
+            &self.tool,
+            &self.label_type,
+            &self.project,
+            &self.lot,
+            &self.serial,
+            &self.plaintext,
+            &self.content,
+            &self.file,
+        ];
+        query.split_whitespace().all(|term| {
+            let term = term.to_lowercase();
+            fields.iter().any(|f| f.to_lowercase().contains(&term))
+        })
+    }
+}
+
+/// 当前操作系统登录用户
+pub fn operator() -> String {
+    std::env::var("USERNAME")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// 审计文件
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        AuditLog::new(AUDIT_PATH)
+    }
+}
+
+impl AuditLog {
+    pub fn new(path: impl AsRef<Path>) -> AuditLog {
+        AuditLog {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn head_path(&self) -> PathBuf {
+        self.path.with_extension("head")
+    }
+
+    fn read_head(&self) -> Result<Option<Head>, String> {
+        match std::fs::read_to_string(self.head_path()) {
+            Ok(s) => serde_json::from_str(&s)
+                .map(Some)
+                .map_err(|e| format!("审计链尾文件损坏: {e}")),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("读取审计链尾失败: {e}")),
+        }
+    }
+
+    /// 读取日志全文，文件不存在时为空
+    fn read_data(&self) -> Result<String, String> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(s) => Ok(s),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(format!("读取审计记录失败: {e}")),
+        }
+    }
+
+    /// 接在链尾追加一批记录并刷到磁盘，传入记录的哈希字段会被重新计算
+    pub fn append(&self, records: &[AuditRecord]) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let _lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("lock"))
+            .and_then(|f| f.lock().map(|_| f))
+            .map_err(|e| format!("锁定审计记录失败: {e}"))?;
+        let mut head = catch_up(self.read_head()?.unwrap_or_default(), &self.read_data()?);
+        let mut data = String::new();
+        for r in records {
+            let mut r = AuditRecord {
+                prev_hash: head.hash.clone(),
+                ..r.clone()
+            };
+            r.hash = r.digest();
+            data.push_str(&serde_json::to_string(&r).map_err(|e| e.to_string())?);
+            data.push('\n');
+            head.records += 1;
+            head.hash = r.hash;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("打开审计记录失败: {e}"))?;
+        file.write_all(data.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| format!("写入审计记录失败: {e}"))?;
+        let head = serde_json::to_string(&head).map_err(|e| e.to_string())?;
+        let tmp = self.path.with_extension("head.tmp");
+        std::fs::write(&tmp, head)
+            .and_then(|_| std::fs::rename(&tmp, self.head_path()))
+            .map_err(|e| format!("写入审计链尾失败: {e}"))
+    }
+
+    /// 逐条检查哈希链，返回记录数；第一处断裂以行号报告
+    pub fn verify(&self) -> Result<usize, String> {
+        let data = self.read_data()?;
+        let mut prev = String::new();
+        let mut count = 0;
+        for (i, line) in data.lines().enumerate() {
+            let line_no = i + 1;
+            let r: AuditRecord = serde_json::from_str(line)
+                .map_err(|e| format!("第 {line_no} 行无法解析，记录被修改: {e}"))?;
+            if r.prev_hash != prev {
+                return Err(format!(
+                    "第 {line_no} 行与上一条不衔接，之前的记录被删除、插入或调换了顺序"
+                ));
+            }
+            if r.hash != r.digest() {
+                return Err(format!("第 {line_no} 行内容与哈希不符，记录被修改"));
+            }
+            prev = r.hash;
+            count += 1;
+        }
+        let head = catch_up(self.read_head()?.unwrap_or_default(), &data);
+        if head.records != count || head.hash != prev {
+            return Err(format!(
+                "链尾应有 {} 条记录，实际 {count} 条，末尾记录被删除或替换",
+                head.records
+            ));
+        }
+        Ok(count)
+    }
+
+    /// 读取全部记录，按写入顺序；文件不存在时为空
+    pub fn load(&self) -> Result<Vec<AuditRecord>, String> {
+        let data = match std::fs::read_to_string(&self.path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("读取审计记录失败: {e}")),
+        };
+        Ok(data
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// 匹配 `query` 的记录，最新的在前
+    pub fn search(&self, query: &str) -> Result<Vec<AuditRecord>, String> {
+        let mut found: Vec<AuditRecord> = self
+            .load()?
+            .into_iter()
+            .filter(|r| r.matches(query))
+            .collect();
+        found.reverse();
+        Ok(found)
+    }
+}
+
+/// 把日志中紧接在链尾之后、哈希正确的记录补进链尾。追加时在写完记录、换链尾
+/// 之前崩溃就会留下这样的记录；不衔接的记录保持原样，交给校验报告。
+fn catch_up(mut head: Head, data: &str) -> Head {
+    for line in data.lines().skip(head.records) {
+        let Ok(r) = serde_json::from_str::<AuditRecord>(line) else {
+            break;
+        };
+        if r.prev_hash != head.hash || r.hash != r.digest() {
+            break;
+        }
+        head.records += 1;
+        head.hash = r.hash;
+    }
+    head
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(serial: &str) -> AuditRecord {
+        AuditRecord {
+            lot: "L2501".to_string(),
+            serial: serial.to_string(),
+            content: format!("content-{serial}"),
+            ..AuditRecord::now("RL-CLIA", "试剂")
+        }
+    }
+
+    /// 在临时目录写入三条记录，返回日志和每行文本
+    fn chained_log(name: &str) -> (AuditLog, Vec<String>) {
+        let dir = std::env::temp_dir().join(format!("audit-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let log = AuditLog::new(dir.join("audit.jsonl"));
+        log.append(&[record("0001"), record("0002")]).unwrap();
+        log.append(&[record("0003")]).unwrap();
+        let lines = std::fs::read_to_string(&log.path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        (log, lines)
+    }
+
+    fn rewrite(log: &AuditLog, lines: &[String]) {
+        std::fs::write(&log.path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn intact_chain_verifies() {
+        let (log, _) = chained_log("intact");
+        assert_eq!(log.verify(), Ok(3));
+        assert_eq!(log.search("0002").unwrap()[0].serial, "0002");
+    }
+
+    #[test]
+    fn detects_edit() {
+        let (log, mut lines) = chained_log("edit");
+        lines[1] = lines[1].replace("content-0002", "content-9999");
+        rewrite(&log, &lines);
+        assert!(log.verify().unwrap_err().starts_with("第 2 行"));
+    }
+
+    #[test]
+    fn detects_deletion_and_reordering() {
+        let (log, lines) = chained_log("delete");
+        rewrite(&log, &[lines[0].clone(), lines[2].clone()]);
+        assert!(log.verify().unwrap_err().starts_with("第 2 行"));
+
+        rewrite(
+            &log,
+            &[lines[1].clone(), lines[0].clone(), lines[2].clone()],
+        );
+        assert!(log.verify().unwrap_err().starts_with("第 1 行"));
+
+        rewrite(&log, &lines[..2]);
+        assert!(log.verify().unwrap_err().contains("末尾"));
+    }
+
+    #[test]
+    fn recovers_head_left_behind_by_a_crash() {
+        let (log, _) = chained_log("crash");
+        let head = std::fs::read_to_string(log.head_path()).unwrap();
+        log.append(&[record("0004"), record("0005")]).unwrap();
+        // 记录已写入，链尾还停在追加之前
+        std::fs::write(log.head_path(), head).unwrap();
+        assert_eq!(log.verify(), Ok(5));
+
+        log.append(&[record("0006")]).unwrap();
+        assert_eq!(log.verify(), Ok(6));
+        let records = log.load().unwrap();
+        assert_eq!(records[5].prev_hash, records[4].hash);
+    }
+
+    #[test]
+    fn extra_records_must_chain_onto_the_head() {
+        let (log, lines) = chained_log("forged");
+        let forged = lines[2].replace("content-0003", "content-9999");
+        rewrite(
+            &log,
+            &[lines[0].clone(), lines[1].clone(), lines[2].clone(), forged],
+        );
+        assert!(log.verify().unwrap_err().starts_with("第 4 行"));
+    }
+
+    #[test]
+    fn concurrent_appends_keep_one_chain() {
+        let (log, _) = chained_log("concurrent");
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let log = log.clone();
+                std::thread::spawn(move || {
+                    for j in 0..5 {
+                        log.append(&[record(&format!("{i}{j}"))]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        assert_eq!(log.verify(), Ok(43));
+    }
+
+    #[test]
+    fn export_fields_do_not_change_existing_hashes() {
+        let r = record("0001");
+        let json = serde_json::to_string(&r).unwrap();
+        assert!(!json.contains("\"file\"") && !json.contains("\"row\""));
+    }
+}
@@ -2,6 +2,15 @@
 //!
 //! 这是一个用于学习 Rust 的项目库
 
+pub mod audit;
+pub mod manifest;
+pub mod mono;
+pub mod zpl;
+
+#[cfg(test)]
+#[path = "../tests/support/golden.rs"]
+mod golden;
+
 /// 一个简单的加法函数示例
 ///
 /// # Examples
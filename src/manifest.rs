@@ -0,0 +1,525 @@
+//! 导出批次的签名清单
+//!
+//! 每次导出都在文件旁写一份 JSON 清单：每个文件的 SHA-256、其中标签的序列号与条码
+//! 内容、生成参数，整体用站点持有的 Ed25519 私钥签名。拿到文件的一方用站点公钥
+//! 校验签名，再逐个比对文件哈希，就能确认这批文件正是当初导出的那批。
+//!
+//! 私钥种子用口令加密保存在 [`SITE_KEY_PATH`]，只能由 [`SiteKey::create`] 显式生成；
+//! 公钥另写到 [`TRUSTED_KEYS_PATH`]，把这个文件分发给校验方即可，校验从不需要私钥。
+
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+/// 站点签名私钥位置（口令加密）
+pub const SITE_KEY_PATH: &str = "Setting/site_key.json";
+/// 可信公钥文件：每行一个 Base64 公钥，`#` 开头的行为注释
+pub const TRUSTED_KEYS_PATH: &str = "Setting/site_key.pub";
+/// 整个文件夹导出时清单文件名的前缀，完整文件名见 [`folder_manifest_path`]
+pub const MANIFEST_PREFIX: &str = "manifest";
+
+/// 文件夹校验时检查是否漏列的扩展名
+const EXPORT_EXTENSIONS: [&str; 3] = ["png", "bmp", "pdf"];
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+/// 站点 Ed25519 签名密钥
+pub struct SiteKey {
+    signing: SigningKey,
+}
+
+/// 密钥文件：公钥明文，32 字节种子用口令派生的密钥加密
+#[derive(Serialize, Deserialize)]
+struct SiteKeyFile {
+    version: u32,
+    public_key: String,
+    salt: String,
+    iv: String,
+    data: String,
+}
+
+impl SiteKey {
+    /// 生成新密钥：种子用 `passphrase` 加密写到 `path`，公钥追加到同目录的 `.pub`
+    /// 可信公钥文件。已有密钥时拒绝覆盖，否则之前签发的清单都会失去可信公钥。
+    pub fn create(path: impl AsRef<Path>, passphrase: &str) -> Result<SiteKey, String> {
+        let path = path.as_ref();
+        if passphrase.is_empty() {
+            return Err("站点签名密钥密码不能为空".to_string());
+        }
+        if path.exists() {
+            return Err(format!("站点签名密钥 {} 已存在", path.display()));
+        }
+        let mut seed = [0u8; 32];
+        let mut salt = [0u8; 16];
+        let mut iv = [0u8; 16];
+        rand::rng().fill_bytes(&mut seed);
+        rand::rng().fill_bytes(&mut salt);
+        rand::rng().fill_bytes(&mut iv);
+        let key = SiteKey {
+            signing: SigningKey::from_bytes(&seed),
+        };
+        let mut buf = [0u8; 48];
+        buf[..32].copy_from_slice(&seed);
+        let data = Aes256CbcEnc::new(&derive_key(passphrase, &salt).into(), &iv.into())
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, 32)
+            .map_err(|e| format!("站点签名密钥加密失败: {e}"))?;
+        let file = SiteKeyFile {
+            version: 2,
+            public_key: key.public_key(),
+            salt: STANDARD.encode(salt),
+            iv: STANDARD.encode(iv),
+            data: STANDARD.encode(data),
+        };
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let json = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .and_then(|mut f| std::io::Write::write_all(&mut f, json.as_bytes()))
+            .map_err(|e| format!("保存站点签名密钥失败: {e}"))?;
+        add_trusted_key(path.with_extension("pub"), &key.public_key())?;
+        Ok(key)
+    }
+
+    /// 用口令解锁密钥文件
+    pub fn load(path: impl AsRef<Path>, passphrase: &str) -> Result<SiteKey, String> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("读取站点签名密钥 {} 失败: {e}", path.display()))?;
+        let file: SiteKeyFile =
+            serde_json::from_str(&data).map_err(|e| format!("站点签名密钥格式错误: {e}"))?;
+        let salt = STANDARD
+            .decode(&file.salt)
+            .map_err(|e| format!("站点签名密钥格式错误: {e}"))?;
+        let iv: [u8; 16] = STANDARD
+            .decode(&file.iv)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or("站点签名密钥格式错误")?;
+        let mut buf = STANDARD
+            .decode(&file.data)
+            .map_err(|e| format!("站点签名密钥格式错误: {e}"))?;
+        let seed: [u8; 32] = Aes256CbcDec::new(&derive_key(passphrase, &salt).into(), &iv.into())
+            .decrypt_padded_mut::<Pkcs7>(&mut buf)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or("站点签名密钥密码错误或文件已损坏")?;
+        let key = SiteKey {
+            signing: SigningKey::from_bytes(&seed),
+        };
+        if key.public_key() != file.public_key {
+            return Err("站点签名密钥密码错误或文件已损坏".to_string());
+        }
+        Ok(key)
+    }
+
+    /// 公钥，Base64
+    pub fn public_key(&self) -> String {
+        STANDARD.encode(self.signing.verifying_key().to_bytes())
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+/// 读取可信公钥文件
+pub fn trusted_keys(path: impl AsRef<Path>) -> Result<Vec<String>, String> {
+    let path = path.as_ref();
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| format!("读取可信公钥 {} 失败: {e}", path.display()))?;
+    Ok(data
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// 把公钥加入可信公钥文件，已有时不重复添加
+fn add_trusted_key(path: impl AsRef<Path>, public_key: &str) -> Result<(), String> {
+    let path = path.as_ref();
+    let mut keys = if path.exists() {
+        trusted_keys(path)?
+    } else {
+        Vec::new()
+    };
+    if !keys.iter().any(|k| k == public_key) {
+        keys.push(public_key.to_string());
+    }
+    std::fs::write(path, keys.join("\n") + "\n").map_err(|e| format!("保存可信公钥失败: {e}"))
+}
+
+/// 文件中的一张标签
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestLabel {
+    pub serial: String,
+    pub content: String,
+}
+
+/// 清单中的一个文件；PNG 只含一张标签，PDF 含整批
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// 相对清单所在文件夹的文件名
+    pub file: String,
+    pub sha256: String,
+    pub labels: Vec<ManifestLabel>,
+    pub parameters: BTreeMap<String, String>,
+}
+
+/// 一次导出的清单
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub tool: String,
+    pub created: String,
+    pub operator: String,
+    pub files: Vec<ManifestFile>,
+    /// 签名公钥，Base64
+    #[serde(default)]
+    pub public_key: String,
+    /// 对除本字段外整个清单的 Ed25519 签名，Base64
+    #[serde(default)]
+    pub signature: String,
+}
+
+/// 由键值对构造参数表
+pub fn parameters(pairs: &[(&str, String)]) -> BTreeMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect()
+}
+
+/// 单个文件导出（如 PDF）的清单位置：`批次.pdf` → `批次.manifest.json`
+pub fn manifest_path_for(file: &Path) -> PathBuf {
+    file.with_extension(format!("{MANIFEST_PREFIX}.json"))
+}
+
+/// 文件夹导出的清单位置：`manifest_YYYYMMDD_HHMMSS.json`，同名已存在时加序号，
+/// 所以同一文件夹多次导出各有各的清单。
+pub fn folder_manifest_path(dir: &Path) -> PathBuf {
+    let stem = format!(
+        "{MANIFEST_PREFIX}_{}",
+        chrono::Local::now().format("%Y%m%d_%H%M%S")
+    );
+    let mut path = dir.join(format!("{stem}.json"));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{stem}_{n}.json"));
+        n += 1;
+    }
+    path
+}
+
+/// 文件夹清单：`manifest*.json`，包括旧版本的 `manifest.json`
+fn is_folder_manifest(path: &Path) -> bool {
+    path.file_name()
+        .map(|n| n.to_string_lossy())
+        .is_some_and(|n| n.starts_with(MANIFEST_PREFIX) && n.ends_with(".json"))
+}
+
+fn sha256_file(path: &Path) -> Result<String, String> {
+    let data = std::fs::read(path).map_err(|e| format!("读取 {} 失败: {e}", path.display()))?;
+    Ok(format!("{:x}", Sha256::digest(&data)))
+}
+
+impl Manifest {
+    pub fn new(tool: &str) -> Manifest {
+        Manifest {
+            tool: tool.to_string(),
+            created: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            operator: crate::audit::operator(),
+            files: Vec::new(),
+            public_key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    /// 登记一个已写出的文件，哈希从磁盘读取
+    pub fn add_file(
+        &mut self,
+        path: &Path,
+        labels: Vec<ManifestLabel>,
+        parameters: BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        let file = path
+            .file_name()
+            .ok_or_else(|| format!("无效的文件路径: {}", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+        self.files.push(ManifestFile {
+            file,
+            sha256: sha256_file(path)?,
+            labels,
+            parameters,
+        });
+        Ok(())
+    }
+
+    /// 参与签名的字节：签名字段置空后的 JSON
+    fn signed_bytes(&self) -> Vec<u8> {
+        let unsigned = Manifest {
+            signature: String::new(),
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).unwrap_or_default()
+    }
+
+    /// 用站点密钥签名后写到 `path`
+    pub fn sign_and_save(&mut self, key: &SiteKey, path: &Path) -> Result<(), String> {
+        self.public_key = key.public_key();
+        self.signature = STANDARD.encode(key.signing.sign(&self.signed_bytes()).to_bytes());
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("写入清单失败: {e}"))
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| format!("读取清单失败: {e}"))?;
+        serde_json::from_str(&data).map_err(|e| format!("清单格式错误: {e}"))
+    }
+
+    /// 检查签名来自 `trusted` 中的某个公钥且清单未被改动
+    pub fn verify_signature(&self, trusted: &[String]) -> Result<(), String> {
+        if !trusted.contains(&self.public_key) {
+            return Err("清单不是由可信公钥签名".to_string());
+        }
+        let key: [u8; 32] = STANDARD
+            .decode(&self.public_key)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or("清单公钥格式错误")?;
+        let key = VerifyingKey::from_bytes(&key).map_err(|_| "清单公钥无效")?;
+        let signature = STANDARD
+            .decode(&self.signature)
+            .ok()
+            .and_then(|b| Signature::from_slice(&b).ok())
+            .ok_or("清单签名格式错误")?;
+        key.verify_strict(&self.signed_bytes(), &signature)
+            .map_err(|_| "清单签名无效，内容已被修改".to_string())
+    }
+
+    /// 逐个比对 `dir` 中列出的文件；给出 `listed_elsewhere` 时，文件夹里既不在本清单、
+    /// 也不在其中的图片 / PDF 也算不符。返回不符项，空表示全部一致。
+    pub fn check_files(&self, dir: &Path, listed_elsewhere: Option<&[String]>) -> Vec<String> {
+        let mut problems = Vec::new();
+        for f in &self.files {
+            match sha256_file(&dir.join(&f.file)) {
+                Ok(hash) if hash == f.sha256 => {}
+                Ok(_) => problems.push(format!("{} 内容与清单不符", f.file)),
+                Err(_) => problems.push(format!("{} 缺失", f.file)),
+            }
+        }
+        if let Some(listed) = listed_elsewhere
+            && let Ok(entries) = std::fs::read_dir(dir)
+        {
+            let mut extra: Vec<String> = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| {
+                    let ext = Path::new(name)
+                        .extension()
+                        .map(|e| e.to_string_lossy().to_lowercase())
+                        .unwrap_or_default();
+                    EXPORT_EXTENSIONS.contains(&ext.as_str())
+                        && !self.files.iter().any(|f| &f.file == name)
+                        && !listed.contains(name)
+                })
+                .collect();
+            extra.sort();
+            problems.extend(extra.into_iter().map(|name| format!("{name} 未列入清单")));
+        }
+        problems
+    }
+}
+
+/// 用站点密钥为刚导出的文件签名并写出清单
+pub fn write_manifest(mut manifest: Manifest, path: &Path, key: &SiteKey) -> Result<(), String> {
+    manifest.sign_and_save(key, path)
+}
+
+/// 校验模式：用可信公钥（[`TRUSTED_KEYS_PATH`]）检查清单签名，再比对清单所在文件夹
+/// 中的文件。文件夹清单还会检查多出的文件，同一文件夹里其他可信清单列出的文件不算。
+/// 返回核对通过的文件数。
+pub fn verify_manifest(path: &Path) -> Result<usize, String> {
+    verify_with(path, &trusted_keys(TRUSTED_KEYS_PATH)?)
+}
+
+fn verify_with(path: &Path, trusted: &[String]) -> Result<usize, String> {
+    let manifest = Manifest::load(path)?;
+    manifest.verify_signature(trusted)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let listed_elsewhere = is_folder_manifest(path).then(|| {
+        std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p != path && p.extension().is_some_and(|e| e == "json"))
+            .filter_map(|p| Manifest::load(&p).ok())
+            .filter(|m| m.verify_signature(trusted).is_ok())
+            .flat_map(|m| m.files.into_iter().map(|f| f.file))
+            .collect::<Vec<_>>()
+    });
+    let problems = manifest.check_files(dir, listed_elsewhere.as_deref());
+    if problems.is_empty() {
+        Ok(manifest.files.len())
+    } else {
+        Err(problems.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("manifest-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 在 `dir` 写一个文件并为它签一份文件夹清单
+    fn export(dir: &Path, file: &str, key: &SiteKey) -> PathBuf {
+        let path = dir.join(file);
+        std::fs::write(&path, file).unwrap();
+        let mut manifest = Manifest::new("test");
+        manifest
+            .add_file(&path, Vec::new(), BTreeMap::new())
+            .unwrap();
+        let manifest_path = folder_manifest_path(dir);
+        write_manifest(manifest, &manifest_path, key).unwrap();
+        manifest_path
+    }
+
+    #[test]
+    fn site_key_is_encrypted_and_never_overwritten() {
+        let dir = temp_dir("key");
+        let path = dir.join("site_key.json");
+        assert!(SiteKey::create(&path, "").is_err());
+        let key = SiteKey::create(&path, "secret").unwrap();
+
+        let stored = std::fs::read_to_string(&path).unwrap();
+        assert!(!stored.contains("secret"));
+        assert!(SiteKey::load(&path, "wrong").is_err());
+        assert_eq!(
+            SiteKey::load(&path, "secret").unwrap().public_key(),
+            key.public_key()
+        );
+        assert!(SiteKey::create(&path, "other").is_err());
+        assert_eq!(
+            trusted_keys(dir.join("site_key.pub")).unwrap(),
+            [key.public_key()]
+        );
+    }
+
+    #[test]
+    fn exports_into_one_folder_keep_their_own_manifests() {
+        let dir = temp_dir("batches");
+        let key = SiteKey::create(dir.join("site_key.json"), "secret").unwrap();
+        let trusted = [key.public_key()];
+        let first = export(&dir, "01.png", &key);
+        let second = export(&dir, "02.png", &key);
+        assert_ne!(first, second);
+        assert_eq!(verify_with(&first, &trusted), Ok(1));
+        assert_eq!(verify_with(&second, &trusted), Ok(1));
+
+        std::fs::write(dir.join("03.png"), "extra").unwrap();
+        assert!(
+            verify_with(&first, &trusted)
+                .unwrap_err()
+                .contains("03.png")
+        );
+        assert!(verify_with(&first, &["other".to_string()]).is_err());
+    }
+
+    #[test]
+    fn modified_or_missing_files_fail() {
+        let dir = temp_dir("files");
+        let key = SiteKey::create(dir.join("site_key.json"), "secret").unwrap();
+        let trusted = [key.public_key()];
+        let manifest = export(&dir, "01.png", &key);
+
+        std::fs::write(dir.join("01.png"), "changed").unwrap();
+        assert_eq!(
+            verify_with(&manifest, &trusted),
+            Err("01.png 内容与清单不符".to_string())
+        );
+
+        std::fs::remove_file(dir.join("01.png")).unwrap();
+        assert_eq!(
+            verify_with(&manifest, &trusted),
+            Err("01.png 缺失".to_string())
+        );
+    }
+
+    #[test]
+    fn tampered_manifest_fails() {
+        let dir = temp_dir("tamper");
+        let key = SiteKey::create(dir.join("site_key.json"), "secret").unwrap();
+        let trusted = [key.public_key()];
+        let path = export(&dir, "01.png", &key);
+        let signed = Manifest::load(&path).unwrap();
+
+        // 改了内容却沿用原签名
+        let mut edited = signed.clone();
+        edited.files[0].file = "02.png".to_string();
+        std::fs::write(&path, serde_json::to_string(&edited).unwrap()).unwrap();
+        assert!(
+            verify_with(&path, &trusted)
+                .unwrap_err()
+                .contains("签名无效")
+        );
+
+        // 签名本身被改动一位
+        let mut forged = signed.clone();
+        let mut bytes = STANDARD.decode(&forged.signature).unwrap();
+        bytes[0] ^= 1;
+        forged.signature = STANDARD.encode(bytes);
+        std::fs::write(&path, serde_json::to_string(&forged).unwrap()).unwrap();
+        assert!(
+            verify_with(&path, &trusted)
+                .unwrap_err()
+                .contains("签名无效")
+        );
+
+        std::fs::write(&path, serde_json::to_string(&signed).unwrap()).unwrap();
+        assert_eq!(verify_with(&path, &trusted), Ok(1));
+    }
+
+    #[test]
+    fn manifest_signed_by_another_key_fails() {
+        let dir = temp_dir("other-key");
+        let site = SiteKey::create(dir.join("site_key.json"), "secret").unwrap();
+        let other = SiteKey::create(dir.join("other_key.json"), "secret").unwrap();
+        let trusted = [site.public_key()];
+        let path = export(&dir, "01.png", &other);
+        assert_eq!(
+            verify_with(&path, &trusted),
+            Err("清单不是由可信公钥签名".to_string())
+        );
+
+        // 换上可信公钥也骗不过签名校验
+        let mut manifest = Manifest::load(&path).unwrap();
+        manifest.public_key = site.public_key();
+        std::fs::write(&path, serde_json::to_string(&manifest).unwrap()).unwrap();
+        assert!(
+            verify_with(&path, &trusted)
+                .unwrap_err()
+                .contains("签名无效")
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! 1 位单色图像输出
+//!
+//! 热敏打印机只有黑白两色，8 位灰度图交给打印驱动后，抗锯齿文字会被不可预知地
+//! 抖动。这里在导出前自行转成 1 位：纯黑、纯白像素（条码模块）原样保留，也不接收
+//! 误差扩散，保证模块边缘清晰；其余灰度像素按阈值二值化，或用 Floyd–Steinberg 抖动。
+
+use image::GrayImage;
+use std::io::Write;
+use std::path::Path;
+
+/// 导出格式下拉框的选项，下标与 [`mono_format`] 对应
+pub const EXPORT_FORMATS: [&str; 3] = ["8位灰度 PNG", "1位 PNG", "1位 BMP"];
+
+/// 1 位文件格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MonoFormat {
+    Png,
+    Bmp,
+}
+
+impl MonoFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            MonoFormat::Png => "png",
+            MonoFormat::Bmp => "bmp",
+        }
+    }
+}
+
+/// 下拉框下标转格式，`None` 表示保持 8 位灰度
+pub fn mono_format(index: usize) -> Option<MonoFormat> {
+    match index {
+        1 => Some(MonoFormat::Png),
+        2 => Some(MonoFormat::Bmp),
+        _ => None,
+    }
+}
+
+/// 二值化参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonoOptions {
+    /// 灰度小于该值的像素为黑色
+    pub threshold: u8,
+    /// 用 Floyd–Steinberg 抖动代替硬阈值
+    pub dither: bool,
+}
+
+impl Default for MonoOptions {
+    fn default() -> Self {
+        MonoOptions {
+            threshold: 128,
+            dither: false,
+        }
+    }
+}
+
+/// 转为只含 0 / 255 的图像
+pub fn to_mono(gray: &GrayImage, opts: &MonoOptions) -> GrayImage {
+    let (w, h) = gray.dimensions();
+    let threshold = opts.threshold as f32;
+    let mut out = GrayImage::new(w, h);
+    // 当前行与下一行的累积误差，左右各留一格免去边界判断
+    let mut err = vec![0f32; w as usize + 2];
+    let mut next = vec![0f32; w as usize + 2];
+    for y in 0..h {
+        for x in 0..w {
+            let v = gray.get_pixel(x, y)[0];
+            let i = x as usize + 1;
+            if v == 0 || v == 255 {
+                out.put_pixel(x, y, image::Luma([v]));
+                continue;
+            }
+            let value = if opts.dither {
+                v as f32 + err[i]
+            } else {
+                v as f32
+            };
+            let black = value < threshold;
+            out.put_pixel(x, y, image::Luma([if black { 0 } else { 255 }]));
+            if opts.dither {
+                let e = value - if black { 0.0 } else { 255.0 };
+                err[i + 1] += e * 7.0 / 16.0;
+                next[i - 1] += e * 3.0 / 16.0;
+                next[i] += e * 5.0 / 16.0;
+                next[i + 1] += e / 16.0;
+            }
+        }
+        std::mem::swap(&mut err, &mut next);
+        next.fill(0.0);
+    }
+    out
+}
+
+/// 二值化后按 `format` 写出 1 位文件，分辨率 `dpi` 写入文件头
+pub fn save_mono(
+    gray: &GrayImage,
+    path: impl AsRef<Path>,
+    dpi: u32,
+    format: MonoFormat,
+    opts: &MonoOptions,
+) -> Result<(), String> {
+    let mono = to_mono(gray, opts);
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut buf = std::io::BufWriter::new(file);
+    let pixels_per_meter = (dpi as f32 / 0.0254).round() as u32;
+    match format {
+        MonoFormat::Png => write_png(&mono, &mut buf, pixels_per_meter),
+        MonoFormat::Bmp => write_bmp(&mono, &mut buf, pixels_per_meter),
+    }
+}
+
+/// 每行按字节对齐打包，高位在前，1 为白
+fn packed_row(mono: &GrayImage, y: u32, row_bytes: usize) -> Vec<u8> {
+    let mut row = vec![0u8; row_bytes];
+    for x in 0..mono.width() {
+        if mono.get_pixel(x, y)[0] != 0 {
+            row[x as usize / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    row
+}
+
+fn write_png(mono: &GrayImage, out: impl Write, pixels_per_meter: u32) -> Result<(), String> {
+    let mut encoder = png::Encoder::new(out, mono.width(), mono.height());
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
+        unit: png::Unit::Meter,
+    }));
+    let row_bytes = mono.width().div_ceil(8) as usize;
+    let data: Vec<u8> = (0..mono.height())
+        .flat_map(|y| packed_row(mono, y, row_bytes))
+        .collect();
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&data).map_err(|e| e.to_string())
+}
+
+/// BITMAPINFOHEADER 格式，调色板 0 = 黑、1 = 白，行自下而上、补齐到 4 字节
+fn write_bmp(mono: &GrayImage, mut out: impl Write, pixels_per_meter: u32) -> Result<(), String> {
+    const HEADER: u32 = 14 + 40 + 8;
+    let row_bytes = mono.width().div_ceil(32) as usize * 4;
+    let image_size = (row_bytes * mono.height() as usize) as u32;
+
+    let mut bmp = Vec::with_capacity((HEADER + image_size) as usize);
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&(HEADER + image_size).to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&HEADER.to_le_bytes());
+    bmp.extend_from_slice(&40u32.to_le_bytes());
+    bmp.extend_from_slice(&(mono.width() as i32).to_le_bytes());
+    bmp.extend_from_slice(&(mono.height() as i32).to_le_bytes());
+    bmp.extend_from_slice(&1u16.to_le_bytes()); // planes
+    bmp.extend_from_slice(&1u16.to_le_bytes()); // bits per pixel
+    bmp.extend_from_slice(&0u32.to_le_bytes()); // BI_RGB
+    bmp.extend_from_slice(&image_size.to_le_bytes());
+    bmp.extend_from_slice(&(pixels_per_meter as i32).to_le_bytes());
+    bmp.extend_from_slice(&(pixels_per_meter as i32).to_le_bytes());
+    bmp.extend_from_slice(&2u32.to_le_bytes()); // colors used
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0]);
+    for y in (0..mono.height()).rev() {
+        bmp.extend_from_slice(&packed_row(mono, y, row_bytes));
+    }
+    out.write_all(&bmp).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 纯黑、纯白之外夹一段灰度渐变，宽度不是 8 的倍数
+    fn sample(w: u32, h: u32) -> GrayImage {
+        GrayImage::from_fn(w, h, |x, y| match (x + y) % 4 {
+            0 => image::Luma([0]),
+            1 => image::Luma([255]),
+            _ => image::Luma([(x * 255 / w.max(1)) as u8]),
+        })
+    }
+
+    /// 解码 1 位 PNG，返回 (宽, 高, 像素)，像素为 0 / 255
+    fn decode_png(data: &[u8]) -> (u32, u32, Vec<u8>) {
+        let decoder = png::Decoder::new(std::io::Cursor::new(data));
+        let mut reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.bit_depth, png::BitDepth::One);
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        assert!(info.palette.is_none());
+        let (w, h) = (info.width, info.height);
+        let row_bytes = w.div_ceil(8) as usize;
+        let mut buf = vec![0u8; row_bytes * h as usize];
+        reader.next_frame(&mut buf).unwrap();
+        let pixels = (0..h as usize)
+            .flat_map(|y| {
+                let row = &buf[y * row_bytes..(y + 1) * row_bytes];
+                (0..w as usize).map(move |x| {
+                    if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                        255
+                    } else {
+                        0
+                    }
+                })
+            })
+            .collect();
+        (w, h, pixels)
+    }
+
+    /// 按文件头解析 1 位 BMP，校验调色板与行补齐，返回 (宽, 高, 像素)
+    fn decode_bmp(data: &[u8]) -> (u32, u32, Vec<u8>) {
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_le_bytes(data[i..i + 2].try_into().unwrap());
+        assert_eq!(&data[..2], b"BM");
+        assert_eq!(u32_at(2) as usize, data.len());
+        let offset = u32_at(10) as usize;
+        assert_eq!(u32_at(14), 40);
+        let (w, h) = (u32_at(18), u32_at(22));
+        assert_eq!(u16_at(26), 1);
+        assert_eq!(u16_at(28), 1, "位深");
+        assert_eq!(u32_at(30), 0, "不压缩");
+        assert_eq!(u32_at(46), 2, "调色板颜色数");
+        assert_eq!(&data[54..62], &[0, 0, 0, 0, 255, 255, 255, 0]);
+        let row_bytes = w.div_ceil(32) as usize * 4;
+        assert_eq!(row_bytes % 4, 0);
+        assert_eq!(data.len(), offset + row_bytes * h as usize);
+        let mut pixels = vec![0u8; (w * h) as usize];
+        for y in 0..h as usize {
+            // 自下而上存储
+            let row = &data[offset + (h as usize - 1 - y) * row_bytes..][..row_bytes];
+            for x in 0..w as usize {
+                let white = row[x / 8] & (0x80 >> (x % 8)) != 0;
+                pixels[y * w as usize + x] = if white { 255 } else { 0 };
+            }
+            // 行尾补齐的位全为 0
+            for x in w as usize..row_bytes * 8 {
+                assert_eq!(row[x / 8] & (0x80 >> (x % 8)), 0, "第 {y} 行补齐位");
+            }
+        }
+        (w, h, pixels)
+    }
+
+    #[test]
+    fn threshold_keeps_pure_pixels_and_splits_gray() {
+        let gray = sample(37, 5);
+        let opts = MonoOptions {
+            threshold: 100,
+            dither: false,
+        };
+        let mono = to_mono(&gray, &opts);
+        for (g, m) in gray.pixels().zip(mono.pixels()) {
+            let expected = match g[0] {
+                0 => 0,
+                255 => 255,
+                v if v < 100 => 0,
+                _ => 255,
+            };
+            assert_eq!(m[0], expected, "灰度 {}", g[0]);
+        }
+    }
+
+    #[test]
+    fn dithering_keeps_modules_and_average_tone() {
+        // 左半为条码模块（纯黑白交替），右半为 50% 灰
+        let gray = GrayImage::from_fn(64, 64, |x, y| {
+            image::Luma([if x < 32 {
+                if (x / 2 + y) % 2 == 0 { 0 } else { 255 }
+            } else {
+                128
+            }])
+        });
+        let opts = MonoOptions {
+            threshold: 128,
+            dither: true,
+        };
+        let mono = to_mono(&gray, &opts);
+        let mut black = 0;
+        for (x, y, p) in mono.enumerate_pixels() {
+            assert!(p[0] == 0 || p[0] == 255);
+            if x < 32 {
+                assert_eq!(p[0], gray.get_pixel(x, y)[0], "模块像素 ({x}, {y})");
+            } else if p[0] == 0 {
+                black += 1;
+            }
+        }
+        // 32×64 的 50% 灰，抖动后约一半为黑
+        assert!((900..1150).contains(&black), "{black}");
+        assert_ne!(mono, to_mono(&gray, &MonoOptions::default()));
+    }
+
+    #[test]
+    fn png_round_trip() {
+        for w in [1, 8, 13, 37] {
+            let mono = to_mono(&sample(w, 6), &MonoOptions::default());
+            let mut data = Vec::new();
+            write_png(&mono, &mut data, 11811).unwrap();
+            let (dw, dh, pixels) = decode_png(&data);
+            assert_eq!((dw, dh), (w, 6));
+            assert_eq!(pixels, mono.as_raw().as_slice(), "宽 {w}");
+        }
+    }
+
+    #[test]
+    fn bmp_round_trip_pads_odd_widths() {
+        for w in [1, 7, 8, 31, 32, 33, 45] {
+            let mono = to_mono(&sample(w, 3), &MonoOptions::default());
+            let mut data = Vec::new();
+            write_bmp(&mono, &mut data, 11811).unwrap();
+            let (dw, dh, pixels) = decode_bmp(&data);
+            assert_eq!((dw, dh), (w, 3));
+            assert_eq!(pixels, mono.as_raw().as_slice(), "宽 {w}");
+        }
+    }
+
+    #[test]
+    fn save_mono_writes_dpi_and_format() {
+        let dir = std::env::temp_dir().join(format!("mono-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let gray = sample(21, 9);
+        let opts = MonoOptions::default();
+        let expected = to_mono(&gray, &opts);
+        for format in [MonoFormat::Png, MonoFormat::Bmp] {
+            let path = dir.join(format!("label.{}", format.extension()));
+            save_mono(&gray, &path, 300, format, &opts).unwrap();
+            let data = std::fs::read(&path).unwrap();
+            let (w, h, pixels) = match format {
+                MonoFormat::Png => decode_png(&data),
+                MonoFormat::Bmp => {
+                    // 300 DPI = 11811 像素/米
+                    assert_eq!(&data[38..42], &11811i32.to_le_bytes());
+                    decode_bmp(&data)
+                }
+            };
+            assert_eq!((w, h), (21, 9));
+            assert_eq!(pixels, expected.as_raw().as_slice());
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
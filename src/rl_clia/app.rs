@@ -1,10 +1,27 @@
 use crate::barcode::{
-    draw_barcode_with_text, generate_barcode, generate_pdf, gray_to_slint_image, ImageType,
+    draw_barcode_with_text, generate_modules, generate_pdf, generate_zpl, gray_to_slint_image,
+    save_png, verify_scan, ImageType, PdfSection, VectorLabel,
 };
-use crate::config;
+use crate::batch;
+use crate::config::{self, Project};
 use crate::encryptor;
+use crate::keystore::{Keystore, KEYSTORE_PATH};
+use crate::payload::{CalibrationPayload, ConsumablePayload, Payload, QcPayload, ReagentPayload};
+use crate::serials;
+use crate::sheet::{SheetConfig, SheetTemplate};
+use crate::template::{LabelTemplate, LabelTemplates, TEMPLATE_DPI};
+use crate::validation::Schema;
 use chrono::{Duration, Local};
+use rust_learning::audit::{AuditLog, AuditRecord};
+use rust_learning::manifest::{
+    self, folder_manifest_path, manifest_path_for, parameters, write_manifest, Manifest,
+    ManifestFile, ManifestLabel, SiteKey, SITE_KEY_PATH,
+};
+use rust_learning::mono::{mono_format, save_mono, to_mono, EXPORT_FORMATS};
+use rust_learning::zpl::PRINTER_DPIS;
 use slint::{ModelRc, VecModel};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 slint::include_modules!();
@@ -15,232 +32,716 @@ fn today_str() -> String {
     Local::now().format("%Y-%m-%d").to_string()
 }
 
-fn compute_expire(date: &str, days: i64) -> String {
-    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
-        .map(|d| (d + Duration::days(days)).format("%Y-%m-%d").to_string())
-        .unwrap_or_default()
+/// Expiry date `valid_days` after `prod_date`.
+fn expire_date(prod_date: &str, valid_days: &str) -> Result<chrono::NaiveDate, String> {
+    let prod = chrono::NaiveDate::parse_from_str(prod_date, "%Y-%m-%d")
+        .map_err(|_| format!("生产日期“{prod_date}”格式应为 YYYY-MM-DD"))?;
+    let days: i64 = valid_days
+        .parse()
+        .map_err(|_| format!("有效天数“{valid_days}”只允许输入整数"))?;
+    Ok(prod + Duration::days(days))
+}
+
+/// [`expire_date`] as written on the label and in the payload.
+fn expire_text(prod_date: &str, valid_days: &str) -> Result<String, String> {
+    expire_date(prod_date, valid_days).map(|d| d.format("%Y-%m-%d").to_string())
+}
+
+/// Number of labels to generate, once the schema has checked it.
+fn quantity(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("数量“{value}”只允许输入整数"))
 }
 
-fn generate_serials(count: usize) -> Vec<String> {
-    let d = Local::now().format("%Y%m%d").to_string();
-    (1..=count).map(|i| format!("{d}{:04}", i)).collect()
+fn label_fields<'a>(
+    project: &'a str,
+    lot: &'a str,
+    prod: &'a str,
+    exp: &'a str,
+    test_counts: &'a str,
+) -> [(&'static str, &'a str); 5] {
+    [
+        ("project", project),
+        ("lot", lot),
+        ("prod_date", prod),
+        ("expire_date", exp),
+        ("test_counts", test_counts),
+    ]
 }
 
-fn require_fields(fields: &[(&str, &str)]) -> Result<(), String> {
-    for (name, val) in fields {
-        if val.trim().is_empty() {
-            return Err(format!("「{name}」不能为空"));
+// ── Image generation per type ────────────────────────────────────────
+
+/// One rendered label and the identifiers used to name its export file.
+#[derive(Clone)]
+struct GeneratedLabel {
+    image: image::GrayImage,
+    /// Encoded barcode content.
+    content: String,
+    /// PDF417 modules, one pixel each, for vector PDF output.
+    modules: image::GrayImage,
+    template: LabelTemplate,
+    /// Rendered text of each template line.
+    texts: Vec<String>,
+    serial: String,
+    project: String,
+    lot: String,
+    /// Plaintext recovered by decrypting the content scanned back from `image`.
+    plain: String,
+    /// The serial is a peeked allocator number, issued on first export.
+    allocated: bool,
+}
+
+impl GeneratedLabel {
+    /// Encode `enc` and lay it out with `template`; `fields` fill the text
+    /// placeholders and supply the project and lot used for file names.
+    ///
+    /// The finished image is scanned back and must decode to `enc` and decrypt
+    /// to the same plaintext, so a label that fails never reaches export.
+    fn render(
+        ks: &Keystore,
+        enc: &str,
+        template: &LabelTemplate,
+        fields: &[(&str, &str)],
+        serial: String,
+    ) -> Result<GeneratedLabel, String> {
+        let field = |key: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+                .unwrap_or_default()
+        };
+        let modules = generate_modules(enc)?;
+        let image = draw_barcode_with_text(&modules, template, fields)?;
+        let which = if serial.is_empty() {
+            String::new()
+        } else {
+            format!("序列号 {serial} ")
+        };
+        let scanned = verify_scan(&image, enc).map_err(|e| format!("{which}{e}"))?;
+        let plain = encryptor::decrypt(ks, &scanned)
+            .map_err(|e| format!("{which}扫码内容解密失败: {e}"))?;
+        if plain != encryptor::decrypt(ks, enc)? {
+            return Err(format!("{which}扫码内容解密后与源明文不一致"));
         }
+        Ok(GeneratedLabel {
+            image,
+            content: enc.to_string(),
+            modules,
+            template: template.clone(),
+            texts: template.texts.iter().map(|t| t.render(fields)).collect(),
+            serial,
+            project: field("project"),
+            lot: field("lot"),
+            plain,
+            allocated: false,
+        })
+    }
+
+    /// This label laid out for a `dpi` printer: the template scaled from
+    /// [`TEMPLATE_DPI`] and redrawn, then scanned back like the original.
+    fn at_dpi(&self, dpi: u32) -> Result<(LabelTemplate, image::GrayImage), String> {
+        if dpi == TEMPLATE_DPI {
+            return Ok((self.template.clone(), self.image.clone()));
+        }
+        let mut template = self.template.scaled(dpi);
+        for (el, text) in template.texts.iter_mut().zip(&self.texts) {
+            el.text = text.clone();
+        }
+        let image = draw_barcode_with_text(&self.modules, &template, &[])?;
+        verify_scan(&image, &self.content).map_err(|e| format!("{dpi} DPI: {e}"))?;
+        Ok((template, image))
     }
-    Ok(())
 }
 
-// ── Image generation per type ────────────────────────────────────────
+// ── Form input ───────────────────────────────────────────────────────
+
+/// Reagent inputs as typed, before validation.
+struct ReagentForm {
+    project: String,
+    lot: String,
+    prod_date: String,
+    valid_days: String,
+    test_counts: String,
+    open_days: String,
+    quantity: String,
+    unit: String,
+    curve: [String; 4],
+    range_low: String,
+    range_upper: String,
+    limit_low: String,
+    limit_upper: String,
+}
+
+struct CalibrationForm {
+    project: String,
+    lot: String,
+    prod_date: String,
+    valid_days: String,
+    quantity: String,
+    c1: String,
+    c2: String,
+}
+
+struct ConsumableForm {
+    /// A consumable of the catalog.
+    name: String,
+    lot: String,
+    prod_date: String,
+    valid_days: String,
+    freq: String,
+    open_days: String,
+    quantity: String,
+}
+
+struct QualityForm {
+    project: String,
+    lot: String,
+    prod_date: String,
+    valid_days: String,
+    quantity: String,
+    q1: String,
+    sd1: String,
+    q2: String,
+    sd2: String,
+}
+
+/// Inputs for one batch of labels, from the window or a spreadsheet row.
+enum Form {
+    Reagent(ReagentForm),
+    Calibration(CalibrationForm),
+    Consumable(ConsumableForm),
+    Quality(QualityForm),
+}
+
+impl ReagentForm {
+    fn from_window(w: &RLCLIAWindow, project: &Project) -> ReagentForm {
+        ReagentForm {
+            project: project.name.clone(),
+            lot: w.get_reagent_lot().trim().to_string(),
+            prod_date: w.get_reagent_prod_date().trim().to_string(),
+            valid_days: w.get_reagent_valid_days().trim().to_string(),
+            test_counts: w.get_reagent_test_counts().trim().to_string(),
+            open_days: w.get_reagent_open_days().trim().to_string(),
+            quantity: w.get_reagent_serial_count().trim().to_string(),
+            unit: project
+                .units
+                .get(w.get_reagent_unit_index() as usize)
+                .cloned()
+                .unwrap_or_default(),
+            curve: [
+                w.get_reagent_param_a().trim().to_string(),
+                w.get_reagent_param_b().trim().to_string(),
+                w.get_reagent_param_c().trim().to_string(),
+                w.get_reagent_param_d().trim().to_string(),
+            ],
+            range_low: w.get_reagent_range_low().trim().to_string(),
+            range_upper: w.get_reagent_range_upper().trim().to_string(),
+            limit_low: w.get_reagent_limit_low().trim().to_string(),
+            limit_upper: w.get_reagent_limit_upper().trim().to_string(),
+        }
+    }
+}
+
+impl CalibrationForm {
+    fn from_window(w: &RLCLIAWindow, project: &Project) -> CalibrationForm {
+        CalibrationForm {
+            project: project.name.clone(),
+            lot: w.get_calib_lot().trim().to_string(),
+            prod_date: w.get_calib_prod_date().trim().to_string(),
+            valid_days: w.get_calib_valid_days().trim().to_string(),
+            quantity: w.get_calib_quantity().trim().to_string(),
+            c1: w.get_calib_c1().trim().to_string(),
+            c2: w.get_calib_c2().trim().to_string(),
+        }
+    }
+}
+
+impl QualityForm {
+    fn from_window(w: &RLCLIAWindow, project: &Project) -> QualityForm {
+        QualityForm {
+            project: project.name.clone(),
+            lot: w.get_quality_lot().trim().to_string(),
+            prod_date: w.get_quality_prod_date().trim().to_string(),
+            valid_days: w.get_quality_valid_days().trim().to_string(),
+            quantity: w.get_quality_quantity().trim().to_string(),
+            q1: w.get_quality_q1().trim().to_string(),
+            sd1: w.get_quality_sd1().trim().to_string(),
+            q2: w.get_quality_q2().trim().to_string(),
+            sd2: w.get_quality_sd2().trim().to_string(),
+        }
+    }
+}
+
+/// The configured project at combo box `index`; a nameless one when the
+/// catalog is empty, which validation then rejects.
+fn project_at(proj: &config::ProjectConfig, index: i32) -> Project {
+    proj.at(index)
+        .cloned()
+        .unwrap_or_else(|| Project::new("", ""))
+}
+
+impl Form {
+    /// Read the page for `typ` (`reagent`, `calibration`, `consumable`, `quality`).
+    fn from_window(
+        typ: &str,
+        w: &RLCLIAWindow,
+        proj: &config::ProjectConfig,
+    ) -> Result<Form, String> {
+        Ok(match typ {
+            "reagent" => Form::Reagent(ReagentForm::from_window(
+                w,
+                &project_at(proj, w.get_reagent_project_index()),
+            )),
+            "calibration" => Form::Calibration(CalibrationForm::from_window(
+                w,
+                &project_at(proj, w.get_calib_project_index()),
+            )),
+            "consumable" => Form::Consumable(ConsumableForm {
+                name: proj
+                    .active_consumables()
+                    .get(w.get_consumable_type_index() as usize)
+                    .map(|c| c.name.clone())
+                    .unwrap_or_default(),
+                lot: w.get_consumable_lot().trim().to_string(),
+                prod_date: w.get_consumable_prod_date().trim().to_string(),
+                valid_days: w.get_consumable_valid_days().trim().to_string(),
+                freq: w.get_consumable_freq().trim().to_string(),
+                open_days: w.get_consumable_open_days().trim().to_string(),
+                quantity: w.get_consumable_quantity().trim().to_string(),
+            }),
+            "quality" => Form::Quality(QualityForm::from_window(
+                w,
+                &project_at(proj, w.get_quality_project_index()),
+            )),
+            _ => return Err("未知类型".into()),
+        })
+    }
+
+    /// Read a spreadsheet row; blank optional cells take the project's
+    /// defaults, as the window prefills them.
+    fn from_row(row: &batch::Row, proj: &config::ProjectConfig) -> Result<Form, String> {
+        let defaults = proj
+            .find(&row.get(batch::COL_PROJECT))
+            .cloned()
+            .unwrap_or_else(|| Project::new("", ""));
+        let days = || row.get_or(batch::COL_VALID_DAYS, &defaults.valid_days.to_string());
+        let quantity = || row.get_or(batch::COL_QUANTITY, "1");
+        let open_days = || row.get_or(batch::COL_OPEN_DAYS, &defaults.open_days.to_string());
+        Ok(match batch::type_key(&row.get(batch::COL_TYPE))? {
+            "reagent" => Form::Reagent(ReagentForm {
+                project: row.get(batch::COL_PROJECT),
+                lot: row.get(batch::COL_LOT),
+                prod_date: row.get(batch::COL_PROD_DATE),
+                valid_days: days(),
+                test_counts: row.get_or(batch::COL_TEST_COUNTS, &defaults.test_counts.to_string()),
+                open_days: open_days(),
+                quantity: quantity(),
+                unit: row.get_or(batch::COL_UNIT, &defaults.units[0]),
+                curve: std::array::from_fn(|i| row.get_or(batch::COL_CURVE[i], &defaults.curve[i])),
+                range_low: row.get(batch::COL_RANGE_LOW),
+                range_upper: row.get(batch::COL_RANGE_UPPER),
+                limit_low: row.get(batch::COL_LIMIT_LOW),
+                limit_upper: row.get(batch::COL_LIMIT_UPPER),
+            }),
+            "calibration" => Form::Calibration(CalibrationForm {
+                project: row.get(batch::COL_PROJECT),
+                lot: row.get(batch::COL_LOT),
+                prod_date: row.get(batch::COL_PROD_DATE),
+                valid_days: days(),
+                quantity: quantity(),
+                c1: row.get(batch::COL_C1),
+                c2: row.get(batch::COL_C2),
+            }),
+            "consumable" => {
+                let name = row.get(batch::COL_PROJECT);
+                let names: Vec<&str> = proj
+                    .active_consumables()
+                    .iter()
+                    .map(|c| c.name.as_str())
+                    .collect();
+                if !names.contains(&name.as_str()) {
+                    return Err(format!(
+                        "耗材的项目应为 {}，实际为“{name}”",
+                        names.join(" / ")
+                    ));
+                }
+                Form::Consumable(ConsumableForm {
+                    name,
+                    lot: row.get(batch::COL_LOT),
+                    prod_date: row.get(batch::COL_PROD_DATE),
+                    valid_days: days(),
+                    freq: row.get_or(batch::COL_FREQ, "2000"),
+                    open_days: open_days(),
+                    quantity: quantity(),
+                })
+            }
+            _ => Form::Quality(QualityForm {
+                project: row.get(batch::COL_PROJECT),
+                lot: row.get(batch::COL_LOT),
+                prod_date: row.get(batch::COL_PROD_DATE),
+                valid_days: days(),
+                quantity: quantity(),
+                q1: row.get(batch::COL_Q1),
+                sd1: row.get(batch::COL_SD1),
+                q2: row.get(batch::COL_Q2),
+                sd2: row.get(batch::COL_SD2),
+            }),
+        })
+    }
+
+    /// Validate, compose and render the batch; returns the labels, the
+    /// project name and the type label.
+    fn generate(
+        &self,
+        proj: &config::ProjectConfig,
+        ks: &Keystore,
+        schema: &Schema,
+        templates: &LabelTemplates,
+    ) -> Result<(Vec<GeneratedLabel>, String, String), String> {
+        match self {
+            Form::Reagent(f) => gen_reagent(f, proj, ks, schema, templates),
+            Form::Calibration(f) => gen_calibration(f, proj, ks, schema, templates),
+            Form::Consumable(f) => gen_consumable(f, proj, ks, schema, templates),
+            Form::Quality(f) => gen_quality(f, proj, ks, schema, templates),
+        }
+    }
+}
+
+/// Reagent, calibrator and QC of one project, generated in one step.
+struct KitForm {
+    reagent: ReagentForm,
+    calibration: CalibrationForm,
+    quality: QualityForm,
+}
+
+/// `(type label, labels)` runs of one batch; the kit has one per type.
+type Sections = Vec<(String, Vec<GeneratedLabel>)>;
+
+impl KitForm {
+    /// Read the kit page. The project is picked once, on the reagent combo,
+    /// and the lot set is the kit page's own; the type-specific parameters
+    /// come from the three type pages.
+    fn from_window(w: &RLCLIAWindow, proj: &config::ProjectConfig) -> KitForm {
+        let project = project_at(proj, w.get_reagent_project_index());
+        let mut kit = KitForm {
+            reagent: ReagentForm::from_window(w, &project),
+            calibration: CalibrationForm::from_window(w, &project),
+            quality: QualityForm::from_window(w, &project),
+        };
+        kit.reagent.lot = w.get_kit_reagent_lot().trim().to_string();
+        kit.calibration.lot = w.get_kit_calib_lot().trim().to_string();
+        kit.quality.lot = w.get_kit_quality_lot().trim().to_string();
+        kit
+    }
+
+    /// Check that neither the calibrator nor the QC outlives the reagent,
+    /// then generate the three batches in print order.
+    fn generate(
+        &self,
+        proj: &config::ProjectConfig,
+        ks: &Keystore,
+        schema: &Schema,
+        templates: &LabelTemplates,
+    ) -> Result<Sections, String> {
+        // Validate the dates first, so the comparison is between real dates.
+        let expire = |typ: &str, name: &str, prod: &str, days: &str| {
+            schema
+                .validate(
+                    typ,
+                    &[
+                        ("生产日期", "生产日期", prod),
+                        ("有效天数", "有效天数", days),
+                    ],
+                )
+                .and_then(|_| expire_date(prod, days))
+                .map_err(|e| format!("{name}: {e}"))
+        };
+        let reagent_exp = expire(
+            "reagent",
+            "试剂",
+            &self.reagent.prod_date,
+            &self.reagent.valid_days,
+        )?;
+        let others = [
+            (
+                "校准品",
+                expire(
+                    "calibration",
+                    "校准品",
+                    &self.calibration.prod_date,
+                    &self.calibration.valid_days,
+                )?,
+            ),
+            (
+                "质控品",
+                expire(
+                    "qc",
+                    "质控品",
+                    &self.quality.prod_date,
+                    &self.quality.valid_days,
+                )?,
+            ),
+        ];
+        for (name, exp) in others {
+            if exp > reagent_exp {
+                return Err(format!(
+                    "{name}失效日期 {exp} 晚于试剂失效日期 {reagent_exp}"
+                ));
+            }
+        }
+        let (reagent, _, reagent_label) = gen_reagent(&self.reagent, proj, ks, schema, templates)?;
+        let (calibration, _, calibration_label) =
+            gen_calibration(&self.calibration, proj, ks, schema, templates)?;
+        let (quality, _, quality_label) = gen_quality(&self.quality, proj, ks, schema, templates)?;
+        Ok(vec![
+            (reagent_label, reagent),
+            (calibration_label, calibration),
+            (quality_label, quality),
+        ])
+    }
+}
+
+/// The configured project called `name`; retired projects are refused.
+fn project<'a>(proj: &'a config::ProjectConfig, name: &str) -> Result<&'a Project, String> {
+    match proj.find(name) {
+        Some(p) if p.retired => Err(format!("项目“{name}”已停用")),
+        Some(p) => Ok(p),
+        None => Err(format!("项目“{name}”不在项目配置中")),
+    }
+}
+
+/// Label layout of the consumable called `name`; names no longer in the
+/// catalog print as fluid A.
+fn fluid_image_type(proj: &config::ProjectConfig, name: &str) -> ImageType {
+    match proj.find_consumable(name).map(|c| c.label) {
+        Some(config::FluidLabel::B) => ImageType::ExcitationFluidB,
+        _ => ImageType::ExcitationFluidA,
+    }
+}
 
 fn gen_reagent(
-    w: &RLCLIAWindow,
+    f: &ReagentForm,
     proj: &config::ProjectConfig,
-) -> Result<(Vec<image::GrayImage>, String, String), String> {
-    let idx = w.get_reagent_project_index() as usize;
-    let name = proj.project_name_list.get(idx).cloned().unwrap_or_default();
-    let id = proj.project_id_list.get(idx).cloned().unwrap_or_default();
-    let lot = w.get_reagent_lot().to_string();
-    let prod = w.get_reagent_prod_date().to_string();
-    let days: i64 = w.get_reagent_valid_days().parse().unwrap_or(365);
-    let exp = compute_expire(&prod, days);
-    let counts = w.get_reagent_test_counts().to_string();
-    let open = w.get_reagent_open_days().to_string();
-    let n: usize = w.get_reagent_serial_count().parse().unwrap_or(1);
-    let units = ["pg/mL", "ng/mL", "mg/L", "ng/L", "IU/L"];
-    let unit = units
-        .get(w.get_reagent_unit_index() as usize)
-        .unwrap_or(&"pg/mL");
-    let pa = w.get_reagent_param_a().to_string();
-    let pb = w.get_reagent_param_b().to_string();
-    let pc = w.get_reagent_param_c().to_string();
-    let pd = w.get_reagent_param_d().to_string();
-    let rl = w.get_reagent_range_low().to_string();
-    let ru = w.get_reagent_range_upper().to_string();
-    let ll = w.get_reagent_limit_low().to_string();
-    let lu = w.get_reagent_limit_upper().to_string();
-
-    require_fields(&[
-        ("项目名称", &name),
-        ("试剂批号", &lot),
-        ("生产日期", &prod),
-        ("有效天数", &w.get_reagent_valid_days().to_string()),
-        ("测试/盒", &counts),
-        ("开瓶天数", &open),
-        ("数量", &w.get_reagent_serial_count().to_string()),
-        ("曲线参数a", &pa),
-        ("曲线参数b", &pb),
-        ("曲线参数c", &pc),
-        ("曲线参数d", &pd),
-        ("范围下限", &rl),
-        ("范围上限", &ru),
-        ("限值下限", &ll),
-        ("限值上限", &lu),
-    ])?;
-
-    let serials = generate_serials(n);
-    let mut imgs = Vec::new();
-    for s in &serials {
+    ks: &Keystore,
+    schema: &Schema,
+    templates: &LabelTemplates,
+) -> Result<(Vec<GeneratedLabel>, String, String), String> {
+    let [pa, pb, pc, pd] = &f.curve;
+
+    schema.validate(
+        "reagent",
+        &[
+            ("项目名称", "项目名称", &f.project),
+            ("试剂批号", "试剂批号", &f.lot),
+            ("生产日期", "生产日期", &f.prod_date),
+            ("有效天数", "有效天数", &f.valid_days),
+            ("测试/盒", "可测次数", &f.test_counts),
+            ("开瓶天数", "开瓶有效天数", &f.open_days),
+            ("数量", "瓶数（生成个数）", &f.quantity),
+            ("曲线参数a", "曲线参数", pa),
+            ("曲线参数b", "曲线参数", pb),
+            ("曲线参数c", "曲线参数", pc),
+            ("曲线参数d", "曲线参数", pd),
+            ("范围下限", "结果参数", &f.range_low),
+            ("范围上限", "结果参数", &f.range_upper),
+            ("限值下限", "结果参数", &f.limit_low),
+            ("限值上限", "结果参数", &f.limit_upper),
+        ],
+    )?;
+    let exp = expire_text(&f.prod_date, &f.valid_days)?;
+    let n = quantity(&f.quantity)?;
+    let p = project(proj, &f.project)?;
+    if !p.units.contains(&f.unit) {
+        return Err(format!(
+            "单位“{}”不是项目“{}”的可选单位（{}）",
+            f.unit,
+            f.project,
+            p.units.join(" / ")
+        ));
+    }
+
+    let serials = serials::peek_serials(&f.lot, n)?;
+    let mut labels = Vec::new();
+    for s in serials {
         let enc = encryptor::compose_reagent(
-            &name, &id, &lot, &prod, &exp, &counts, &open, "direct", s, unit, &pa, &pb, &pc, &pd,
-            &rl, &ru, &ll, &lu,
+            ks,
+            proj,
+            &ReagentPayload {
+                project_name: f.project.clone(),
+                project_id: p.id.clone(),
+                lot: f.lot.clone(),
+                prod_date: f.prod_date.clone(),
+                expire_date: exp.clone(),
+                test_counts: f.test_counts.clone(),
+                open_days: f.open_days.clone(),
+                reaction_mode: p.reaction_mode.clone(),
+                serial_number: s.clone(),
+                unit: f.unit.clone(),
+                curve_a: pa.clone(),
+                curve_b: pb.clone(),
+                curve_c: pc.clone(),
+                curve_d: pd.clone(),
+                range_low: f.range_low.clone(),
+                range_upper: f.range_upper.clone(),
+                limit_low: f.limit_low.clone(),
+                limit_upper: f.limit_upper.clone(),
+            },
         )?;
-        let bc = generate_barcode(&enc)?;
-        imgs.push(draw_barcode_with_text(
-            &bc,
-            ImageType::ReagentInformation,
-            &name,
-            &lot,
-            &prod,
-            &exp,
-            &counts,
-        ));
+        let mut label = GeneratedLabel::render(
+            ks,
+            &enc,
+            templates.get(ImageType::ReagentInformation),
+            &label_fields(&f.project, &f.lot, &f.prod_date, &exp, &f.test_counts),
+            s,
+        )?;
+        label.allocated = true;
+        labels.push(label);
     }
-    Ok((imgs, name, "试剂".into()))
+    Ok((labels, f.project.clone(), "试剂".into()))
 }
 
 fn gen_calibration(
-    w: &RLCLIAWindow,
+    f: &CalibrationForm,
     proj: &config::ProjectConfig,
-) -> Result<(Vec<image::GrayImage>, String, String), String> {
-    let idx = w.get_calib_project_index() as usize;
-    let name = proj.project_name_list.get(idx).cloned().unwrap_or_default();
-    let id = proj.project_id_list.get(idx).cloned().unwrap_or_default();
-    let lot = w.get_calib_lot().to_string();
-    let prod = w.get_calib_prod_date().to_string();
-    let days: i64 = w.get_calib_valid_days().parse().unwrap_or(365);
-    let exp = compute_expire(&prod, days);
-    let n: usize = w.get_calib_quantity().parse().unwrap_or(1);
-    let c1 = w.get_calib_c1().to_string();
-    let c2 = w.get_calib_c2().to_string();
-
-    require_fields(&[
-        ("项目名称", &name),
-        ("校准批号", &lot),
-        ("生产日期", &prod),
-        ("有效天数", &w.get_calib_valid_days().to_string()),
-        ("数量", &w.get_calib_quantity().to_string()),
-        ("C1发光值", &c1),
-        ("C2发光值", &c2),
-    ])?;
-
-    let mut imgs = Vec::new();
-    for _ in 0..n {
-        let enc =
-            encryptor::compose_calibration(&name, &id, &lot, &prod, &exp, "direct", &c1, &c2)?;
-        let bc = generate_barcode(&enc)?;
-        imgs.push(draw_barcode_with_text(
-            &bc,
-            ImageType::CalibrationProduct,
-            &name,
-            &lot,
-            &prod,
-            &exp,
-            "",
-        ));
-    }
-    Ok((imgs, name, "校准品".into()))
-}
-
-fn gen_consumable(w: &RLCLIAWindow) -> Result<(Vec<image::GrayImage>, String, String), String> {
-    let types = ["激发液A", "激发液B"];
-    let ti = w.get_consumable_type_index() as usize;
-    let tn = types.get(ti).unwrap_or(&"激发液A");
-    let lot = w.get_consumable_lot().to_string();
-    let prod = w.get_consumable_prod_date().to_string();
-    let days: i64 = w.get_consumable_valid_days().parse().unwrap_or(365);
-    let exp = compute_expire(&prod, days);
-    let freq = w.get_consumable_freq().to_string();
-    let open = w.get_consumable_open_days().to_string();
-    let n: usize = w.get_consumable_quantity().parse().unwrap_or(1);
-
-    require_fields(&[
-        ("耗材批号", &lot),
-        ("生产日期", &prod),
-        ("有效天数", &w.get_consumable_valid_days().to_string()),
-        ("可用频次", &freq),
-        ("开瓶天数", &open),
-        ("数量", &w.get_consumable_quantity().to_string()),
-    ])?;
-
-    let mut imgs = Vec::new();
-    for _ in 0..n {
-        let enc = encryptor::compose_consumable(tn, &lot, &prod, &exp, &freq, &open)?;
-        let bc = generate_barcode(&enc)?;
-        let it = if ti == 0 {
-            ImageType::ExcitationFluidA
-        } else {
-            ImageType::ExcitationFluidB
-        };
-        imgs.push(draw_barcode_with_text(&bc, it, tn, &lot, &prod, &exp, ""));
+    ks: &Keystore,
+    schema: &Schema,
+    templates: &LabelTemplates,
+) -> Result<(Vec<GeneratedLabel>, String, String), String> {
+    schema.validate(
+        "calibration",
+        &[
+            ("项目名称", "项目名称", &f.project),
+            ("校准批号", "校准品批号", &f.lot),
+            ("生产日期", "生产日期", &f.prod_date),
+            ("有效天数", "有效天数", &f.valid_days),
+            ("数量", "瓶数（生成个数）", &f.quantity),
+            ("C1发光值", "C0浓度值", &f.c1),
+            ("C2发光值", "C1浓度值", &f.c2),
+        ],
+    )?;
+    let exp = expire_text(&f.prod_date, &f.valid_days)?;
+    let n = quantity(&f.quantity)?;
+
+    let p = project(proj, &f.project)?;
+    let payload = CalibrationPayload {
+        project_name: f.project.clone(),
+        project_id: p.id.clone(),
+        lot: f.lot.clone(),
+        prod_date: f.prod_date.clone(),
+        expire_date: exp.clone(),
+        reaction_mode: p.reaction_mode.clone(),
+        c1: f.c1.clone(),
+        c2: f.c2.clone(),
+    };
+    let mut labels = Vec::new();
+    for i in 0..n {
+        let enc = encryptor::compose_calibration(ks, proj, &payload)?;
+        labels.push(GeneratedLabel::render(
+            ks,
+            &enc,
+            templates.get(ImageType::CalibrationProduct),
+            &label_fields(&f.project, &f.lot, &f.prod_date, &exp, ""),
+            format!("{:04}", i + 1),
+        )?);
     }
-    Ok((imgs, tn.to_string(), "耗材".into()))
+    Ok((labels, f.project.clone(), "校准品".into()))
 }
 
-fn gen_quality(
-    w: &RLCLIAWindow,
+fn gen_consumable(
+    f: &ConsumableForm,
     proj: &config::ProjectConfig,
-) -> Result<(Vec<image::GrayImage>, String, String), String> {
-    let idx = w.get_quality_project_index() as usize;
-    let name = proj.project_name_list.get(idx).cloned().unwrap_or_default();
-    let id = proj.project_id_list.get(idx).cloned().unwrap_or_default();
-    let lot = w.get_quality_lot().to_string();
-    let prod = w.get_quality_prod_date().to_string();
-    let days: i64 = w.get_quality_valid_days().parse().unwrap_or(365);
-    let exp = compute_expire(&prod, days);
-    let n: usize = w.get_quality_quantity().parse().unwrap_or(1);
-    let q1 = w.get_quality_q1().to_string();
-    let sd1 = w.get_quality_sd1().to_string();
-    let q2 = w.get_quality_q2().to_string();
-    let sd2 = w.get_quality_sd2().to_string();
-
-    require_fields(&[
-        ("项目名称", &name),
-        ("质控批号", &lot),
-        ("生产日期", &prod),
-        ("有效天数", &w.get_quality_valid_days().to_string()),
-        ("数量", &w.get_quality_quantity().to_string()),
-        ("Q1", &q1),
-        ("SD1", &sd1),
-        ("Q2", &q2),
-        ("SD2", &sd2),
-    ])?;
-
-    let mut imgs = Vec::new();
-    for _ in 0..n {
-        let enc = encryptor::compose_quality(
-            &name, &id, &lot, &prod, &exp, "direct", &q1, &sd1, &q2, &sd2,
-        )?;
-        let bc = generate_barcode(&enc)?;
-        imgs.push(draw_barcode_with_text(
-            &bc,
-            ImageType::QualityControl,
-            &name,
-            &lot,
-            &prod,
-            &exp,
-            "",
-        ));
+    ks: &Keystore,
+    schema: &Schema,
+    templates: &LabelTemplates,
+) -> Result<(Vec<GeneratedLabel>, String, String), String> {
+    schema.validate(
+        "consumable",
+        &[
+            ("耗材批号", "批号", &f.lot),
+            ("生产日期", "生产日期", &f.prod_date),
+            ("有效天数", "有效天数", &f.valid_days),
+            ("可用频次", "可用次数", &f.freq),
+            ("开瓶天数", "开瓶有效天数", &f.open_days),
+            ("数量", "瓶数（生成个数）", &f.quantity),
+        ],
+    )?;
+    let exp = expire_text(&f.prod_date, &f.valid_days)?;
+    let n = quantity(&f.quantity)?;
+
+    let payload = ConsumablePayload {
+        name: f.name.clone(),
+        lot: f.lot.clone(),
+        prod_date: f.prod_date.clone(),
+        expire_date: exp.clone(),
+        test_counts: f.freq.clone(),
+        open_days: f.open_days.clone(),
+    };
+    let it = fluid_image_type(proj, &f.name);
+    let mut labels = Vec::new();
+    for i in 0..n {
+        let enc = encryptor::compose_consumable(ks, proj, &payload)?;
+        labels.push(GeneratedLabel::render(
+            ks,
+            &enc,
+            templates.get(it),
+            &label_fields(&f.name, &f.lot, &f.prod_date, &exp, ""),
+            format!("{:04}", i + 1),
+        )?);
     }
-    Ok((imgs, name, "质控品".into()))
+    Ok((labels, f.name.clone(), "耗材".into()))
 }
 
-fn dispatch_generate(
-    typ: &str,
-    w: &RLCLIAWindow,
+fn gen_quality(
+    f: &QualityForm,
     proj: &config::ProjectConfig,
-) -> Result<(Vec<image::GrayImage>, String, String), String> {
-    match typ {
-        "reagent" => gen_reagent(w, proj),
-        "calibration" => gen_calibration(w, proj),
-        "consumable" => gen_consumable(w),
-        "quality" => gen_quality(w, proj),
-        _ => Err("未知类型".into()),
+    ks: &Keystore,
+    schema: &Schema,
+    templates: &LabelTemplates,
+) -> Result<(Vec<GeneratedLabel>, String, String), String> {
+    schema.validate(
+        "qc",
+        &[
+            ("项目名称", "项目名称", &f.project),
+            ("质控批号", "批号", &f.lot),
+            ("生产日期", "生产日期", &f.prod_date),
+            ("有效天数", "有效天数", &f.valid_days),
+            ("数量", "瓶数（生成个数）", &f.quantity),
+            ("Q1", "Q1", &f.q1),
+            ("SD1", "SD1", &f.sd1),
+            ("Q2", "Q2", &f.q2),
+            ("SD2", "SD2", &f.sd2),
+        ],
+    )?;
+    let exp = expire_text(&f.prod_date, &f.valid_days)?;
+    let n = quantity(&f.quantity)?;
+
+    let p = project(proj, &f.project)?;
+    let payload = QcPayload {
+        project_name: f.project.clone(),
+        project_id: p.id.clone(),
+        lot: f.lot.clone(),
+        prod_date: f.prod_date.clone(),
+        expire_date: exp.clone(),
+        reaction_mode: p.reaction_mode.clone(),
+        q1: f.q1.clone(),
+        sd1: f.sd1.clone(),
+        q2: f.q2.clone(),
+        sd2: f.sd2.clone(),
+    };
+    let mut labels = Vec::new();
+    for i in 0..n {
+        let enc = encryptor::compose_quality(ks, proj, &payload)?;
+        labels.push(GeneratedLabel::render(
+            ks,
+            &enc,
+            templates.get(ImageType::QualityControl),
+            &label_fields(&f.project, &f.lot, &f.prod_date, &exp, ""),
+            format!("{:04}", i + 1),
+        )?);
     }
+    Ok((labels, f.project.clone(), "质控品".into()))
 }
 
 fn label_of(typ: &str) -> &'static str {
@@ -249,29 +750,890 @@ fn label_of(typ: &str) -> &'static str {
         "calibration" => "校准品",
         "consumable" => "耗材",
         "quality" => "质控品",
+        "kit" => "套装",
         _ => "条码",
     }
 }
 
-// ── Entry point ──────────────────────────────────────────────────────
+fn vector_labels(labels: &[GeneratedLabel]) -> Vec<VectorLabel<'_>> {
+    labels
+        .iter()
+        .map(|l| VectorLabel {
+            template: &l.template,
+            content: &l.content,
+            modules: &l.modules,
+            texts: &l.texts,
+            image: &l.image,
+        })
+        .collect()
+}
 
-pub fn run() {
-    let proj = config::load_project_config();
-    let window = RLCLIAWindow::new().expect("创建窗口失败");
+/// Name of this tool in the audit trail.
+const TOOL: &str = "RL-CLIA";
 
-    // Populate project combos
-    let names: Vec<slint::SharedString> = proj
-        .project_name_list
+const SITE_KEY_LOCKED: &str = "站点签名密钥未解锁，无法签名导出清单：请输入密码后点击「解锁」，\
+首次使用请先「创建签名密钥」";
+
+/// Home page line describing the manifest signing key.
+fn site_key_state(key: Option<&SiteKey>) -> String {
+    match key {
+        Some(key) => format!("签名公钥: {}", key.public_key()),
+        None if Path::new(SITE_KEY_PATH).exists() => "签名密钥未解锁".into(),
+        None => "尚未创建签名密钥".into(),
+    }
+}
+
+/// Reserve the batch's allocator serials, run `export`, then mark them issued
+/// and audit the labels. A failed export issues and records nothing.
+fn issue_batch<T>(
+    sections: &[(String, Vec<GeneratedLabel>)],
+    export: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+    let serials: Vec<(String, String)> = sections
         .iter()
-        .map(|s| s.as_str().into())
+        .flat_map(|(_, labels)| labels)
+        .filter(|l| l.allocated)
+        .map(|l| (l.lot.clone(), l.serial.clone()))
         .collect();
-    let ids: Vec<slint::SharedString> = proj
-        .project_id_list
+    let reservation = serials::reserve(&serials)?;
+    let out = export()?;
+    reservation.commit()?;
+    for (label, labels) in sections {
+        record_batch(labels, label)?;
+    }
+    Ok(out)
+}
+
+/// Append one audit row per label of a freshly exported batch.
+fn record_batch(labels: &[GeneratedLabel], type_label: &str) -> Result<(), String> {
+    let records: Vec<AuditRecord> = labels
         .iter()
-        .map(|s| s.as_str().into())
+        .map(|l| AuditRecord {
+            project: l.project.clone(),
+            lot: l.lot.clone(),
+            serial: l.serial.clone(),
+            plaintext: l.plain.clone(),
+            content: l.content.clone(),
+            ..AuditRecord::now(TOOL, type_label)
+        })
         .collect();
-    window.set_project_names(ModelRc::new(VecModel::from(names)));
-    window.set_project_ids(ModelRc::new(VecModel::from(ids)));
+    AuditLog::default().append(&records)
+}
+
+/// Rebuild a label from its audit record. The content is decrypted and
+/// scanned back exactly as on first generation.
+fn relabel(
+    ks: &Keystore,
+    proj: &config::ProjectConfig,
+    templates: &LabelTemplates,
+    record: &AuditRecord,
+) -> Result<GeneratedLabel, String> {
+    let payload = encryptor::parse(proj, &record.plaintext)?;
+    let (image_type, prod, exp, counts) = match &payload {
+        Payload::Reagent(p) => (
+            ImageType::ReagentInformation,
+            &p.prod_date,
+            &p.expire_date,
+            p.test_counts.as_str(),
+        ),
+        Payload::Calibration(p) => (
+            ImageType::CalibrationProduct,
+            &p.prod_date,
+            &p.expire_date,
+            "",
+        ),
+        Payload::Consumable(p) => (
+            fluid_image_type(proj, &p.name),
+            &p.prod_date,
+            &p.expire_date,
+            "",
+        ),
+        Payload::Qc(p) => (ImageType::QualityControl, &p.prod_date, &p.expire_date, ""),
+    };
+    GeneratedLabel::render(
+        ks,
+        &record.content,
+        templates.get(image_type),
+        &label_fields(&record.project, &record.lot, prod, exp, counts),
+        record.serial.clone(),
+    )
+}
+
+/// Show search results on the history page.
+fn show_history(w: &RLCLIAWindow, records: &[AuditRecord]) {
+    let column = |f: fn(&AuditRecord) -> &str| -> ModelRc<slint::SharedString> {
+        let v: Vec<slint::SharedString> = records.iter().map(|r| f(r).into()).collect();
+        ModelRc::new(VecModel::from(v))
+    };
+    w.set_history_times(column(|r| r.timestamp.as_str()));
+    w.set_history_operators(column(|r| r.operator.as_str()));
+    w.set_history_tools(column(|r| r.tool.as_str()));
+    w.set_history_types(column(|r| r.label_type.as_str()));
+    w.set_history_projects(column(|r| r.project.as_str()));
+    w.set_history_lots(column(|r| r.lot.as_str()));
+    w.set_history_serials(column(|r| r.serial.as_str()));
+}
+
+/// Fill the `typ` form with the master data of the project just picked.
+fn prefill(w: &RLCLIAWindow, typ: &str, project: &Project) {
+    let days = project.valid_days.to_string().into();
+    match typ {
+        "reagent" => {
+            let units: Vec<slint::SharedString> =
+                project.units.iter().map(|u| u.as_str().into()).collect();
+            w.set_reagent_units(ModelRc::new(VecModel::from(units)));
+            w.set_reagent_unit_index(0);
+            w.set_reagent_valid_days(days);
+            w.set_reagent_test_counts(project.test_counts.to_string().into());
+            w.set_reagent_open_days(project.open_days.to_string().into());
+            let [a, b, c, d] = &project.curve;
+            w.set_reagent_param_a(a.as_str().into());
+            w.set_reagent_param_b(b.as_str().into());
+            w.set_reagent_param_c(c.as_str().into());
+            w.set_reagent_param_d(d.as_str().into());
+        }
+        "calibration" => w.set_calib_valid_days(days),
+        "quality" => w.set_quality_valid_days(days),
+        _ => {}
+    }
+}
+
+/// Fill the preview gallery with every label of a batch.
+fn show_gallery(w: &RLCLIAWindow, labels: &[GeneratedLabel]) {
+    let images: Vec<slint::Image> = labels
+        .iter()
+        .map(|l| gray_to_slint_image(&l.image))
+        .collect();
+    let serials: Vec<slint::SharedString> =
+        labels.iter().map(|l| l.serial.as_str().into()).collect();
+    let texts: Vec<slint::SharedString> = labels.iter().map(|l| l.plain.as_str().into()).collect();
+    w.set_gallery_images(ModelRc::new(VecModel::from(images)));
+    w.set_gallery_serials(ModelRc::new(VecModel::from(serials)));
+    w.set_gallery_texts(ModelRc::new(VecModel::from(texts)));
+}
+
+/// Printer resolution and image format picked in the export settings; a
+/// change is saved.
+fn export_settings(w: &RLCLIAWindow) -> Result<config::ExportConfig, String> {
+    let threshold = w
+        .get_mono_threshold()
+        .trim()
+        .parse::<u8>()
+        .map_err(|_| "二值化阈值必须为 0-255 的整数".to_string())?;
+    let cfg = config::ExportConfig {
+        dpi: PRINTER_DPIS
+            .get(w.get_dpi_index() as usize)
+            .copied()
+            .unwrap_or(TEMPLATE_DPI),
+        format_index: w.get_export_format_index().max(0) as usize,
+        threshold,
+        dither: w.get_mono_dither(),
+    };
+    if cfg != config::load_export_config() {
+        config::save_export_config(&cfg)?;
+    }
+    Ok(cfg)
+}
+
+/// Expand `{type}` `{project}` `{lot}` `{serial}` `{index}` in a file name template.
+fn png_file_name(template: &str, type_label: &str, label: &GeneratedLabel, index: usize) -> String {
+    let name = template
+        .replace("{type}", type_label)
+        .replace("{project}", &label.project)
+        .replace("{lot}", &label.lot)
+        .replace("{serial}", &label.serial)
+        .replace("{index}", &format!("{:02}", index + 1));
+    let safe = safe_file_name(&name);
+    if safe.is_empty() {
+        format!("{:02}", index + 1)
+    } else {
+        safe
+    }
+}
+
+/// Replace characters Windows forbids in file names, and whitespace, with `_`.
+fn safe_file_name(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| match c {
+            '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn manifest_label(label: &GeneratedLabel) -> ManifestLabel {
+    ManifestLabel {
+        serial: label.serial.clone(),
+        content: label.content.clone(),
+    }
+}
+
+/// Write every `(label, type label)` into `dir` as 8-bit PNG, or as 1-bit
+/// PNG / BMP, at the export settings' DPI, followed by a signed manifest.
+fn export_png_batch(
+    labels: &[(&GeneratedLabel, &str)],
+    template: &str,
+    dir: &Path,
+    settings: &config::ExportConfig,
+    key: &SiteKey,
+) -> Result<usize, String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let mut manifest = Manifest::new(TOOL);
+    write_images(
+        labels,
+        template,
+        dir,
+        settings,
+        &mut HashSet::new(),
+        &mut manifest,
+    )?;
+    write_manifest(manifest, &folder_manifest_path(dir), key)?;
+    Ok(labels.len())
+}
+
+/// Image files of [`export_png_batch`], added to `manifest`. `used` holds the
+/// names already taken in `dir`.
+fn write_images(
+    labels: &[(&GeneratedLabel, &str)],
+    template: &str,
+    dir: &Path,
+    settings: &config::ExportConfig,
+    used: &mut HashSet<String>,
+    manifest: &mut Manifest,
+) -> Result<(), String> {
+    for (i, (label, type_label)) in labels.iter().enumerate() {
+        let base = png_file_name(template, type_label, label, i);
+        // A template without {serial}/{index} would otherwise overwrite earlier files.
+        let mut name = base.clone();
+        let mut k = i + 1;
+        while !used.insert(name.clone()) {
+            name = format!("{base}_{k:02}");
+            k += 1;
+        }
+        let (_, image) = label.at_dpi(settings.dpi)?;
+        let mut params = vec![
+            ("type", type_label.to_string()),
+            ("project", label.project.clone()),
+            ("lot", label.lot.clone()),
+            ("dpi", settings.dpi.to_string()),
+        ];
+        let path = match mono_format(settings.format_index) {
+            None => {
+                let path = dir.join(format!("{name}.png"));
+                save_png(&image, &path, settings.dpi)?;
+                params.push(("format", "png".to_string()));
+                path
+            }
+            Some(format) => {
+                let path = dir.join(format!("{name}.{}", format.extension()));
+                // Thresholding can break thin modules, so check the bits that are
+                // written; save_mono keeps pure black/white pixels as they are.
+                let mono = to_mono(&image, &settings.mono_options());
+                verify_scan(&mono, &label.content).map_err(|e| format!("{name} 1位图像: {e}"))?;
+                save_mono(&mono, &path, settings.dpi, format, &settings.mono_options())?;
+                params.push(("format", format!("1-bit {}", format.extension())));
+                params.push(("threshold", settings.threshold.to_string()));
+                params.push(("dither", settings.dither.to_string()));
+                path
+            }
+        };
+        manifest.add_file(&path, vec![manifest_label(label)], parameters(&params))?;
+    }
+    Ok(())
+}
+
+/// Lay a batch out on the named `sheet` as a vector PDF at `path` and add it
+/// to `manifest`. Each section starts on a new page and, when there are
+/// several, gets a bookmark.
+fn write_pdf(
+    sections: &[(String, Vec<GeneratedLabel>)],
+    (sheet_name, sheet): (&str, &SheetTemplate),
+    skip: usize,
+    (ox, oy): (f32, f32),
+    path: &Path,
+    manifest: &mut Manifest,
+) -> Result<(), String> {
+    let pdf_sections: Vec<PdfSection> = sections
+        .iter()
+        .map(|(type_label, labels)| PdfSection {
+            title: match (sections.len(), labels.first()) {
+                (2.., Some(first)) => format!("{type_label} {}", first.lot),
+                _ => String::new(),
+            },
+            labels: vector_labels(labels),
+        })
+        .collect();
+    generate_pdf(
+        &pdf_sections,
+        sheet,
+        skip,
+        (ox, oy),
+        path.to_str().unwrap_or(""),
+    )?;
+    let mut params = batch_params(sections);
+    params.extend([
+        ("sheet", sheet_name.to_string()),
+        ("skip", skip.to_string()),
+        ("offset_mm", format!("{ox},{oy}")),
+    ]);
+    add_batch_file(manifest, path, sections, &params)
+}
+
+/// Write a batch's ZPL job to `path` and add it to `manifest`. `graphic`
+/// records that the labels were sent as 1-bit `^GF` images.
+fn write_zpl(
+    sections: &[(String, Vec<GeneratedLabel>)],
+    zpl: &str,
+    (graphic, settings): (bool, &config::ExportConfig),
+    path: &Path,
+    manifest: &mut Manifest,
+) -> Result<(), String> {
+    std::fs::write(path, zpl).map_err(|e| e.to_string())?;
+    let mut params = batch_params(sections);
+    params.push(("dpi", settings.dpi.to_string()));
+    if graphic {
+        params.push(("format", "zpl ^GF".to_string()));
+        params.push(("threshold", settings.threshold.to_string()));
+        params.push(("dither", settings.dither.to_string()));
+    } else {
+        params.push(("format", "zpl".to_string()));
+    }
+    add_batch_file(manifest, path, sections, &params)
+}
+
+/// Type, project and lot of every section, joined with `+`.
+fn batch_params(sections: &[(String, Vec<GeneratedLabel>)]) -> Vec<(&'static str, String)> {
+    let joined = |f: fn(&(String, Vec<GeneratedLabel>)) -> String| {
+        sections.iter().map(f).collect::<Vec<_>>().join("+")
+    };
+    vec![
+        ("type", joined(|(t, _)| t.clone())),
+        (
+            "project",
+            joined(|(_, l)| l.first().map(|l| l.project.clone()).unwrap_or_default()),
+        ),
+        (
+            "lot",
+            joined(|(_, l)| l.first().map(|l| l.lot.clone()).unwrap_or_default()),
+        ),
+    ]
+}
+
+/// Add a file holding the whole batch to `manifest`.
+fn add_batch_file(
+    manifest: &mut Manifest,
+    path: &Path,
+    sections: &[(String, Vec<GeneratedLabel>)],
+    params: &[(&str, String)],
+) -> Result<(), String> {
+    manifest.add_file(
+        path,
+        sections
+            .iter()
+            .flat_map(|(_, labels)| labels.iter().map(manifest_label))
+            .collect(),
+        parameters(params),
+    )
+}
+
+// ── Batch mode ───────────────────────────────────────────────────────
+
+/// Headless mode: generate every row of a planning spreadsheet into the
+/// output folder as images plus one PDF per row, with a signed manifest and
+/// a report. Returns the process exit code: 0 all rows succeeded, 1 some
+/// rows failed, 2 nothing was generated.
+pub fn run_batch(args: &[String]) -> i32 {
+    let args = match batch::parse_args(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{}", batch::USAGE);
+            return 2;
+        }
+    };
+    match generate_batch(&args) {
+        Ok((total, 0)) => {
+            eprintln!("全部 {total} 行已生成: {}", args.out.display());
+            0
+        }
+        Ok((total, failed)) => {
+            eprintln!(
+                "{total} 行中 {failed} 行失败，详见 {}",
+                args.out.join(batch::REPORT_NAME).display()
+            );
+            1
+        }
+        Err(e) => {
+            eprintln!("错误: {e}");
+            2
+        }
+    }
+}
+
+/// Returns the number of rows and of failed rows.
+fn generate_batch(args: &batch::Args) -> Result<(usize, usize), String> {
+    let rows = batch::read_rows(&args.input)?;
+    // Every batch gets a signed manifest, so the site key is unlocked first.
+    let site_pass = args
+        .site_passphrase
+        .as_deref()
+        .or(args.passphrase.as_deref())
+        .ok_or("签名导出清单需要站点签名密钥，请用 --site-passphrase 或 --passphrase 解锁")?;
+    let site_key = SiteKey::load(SITE_KEY_PATH, site_pass)?;
+    let ks = if Path::new(KEYSTORE_PATH).exists() {
+        let pass = args
+            .passphrase
+            .as_deref()
+            .ok_or("密钥库已启用，请用 --passphrase 解锁")?;
+        Keystore::load(KEYSTORE_PATH, pass)?
+    } else {
+        Keystore::default()
+    };
+    let proj = config::load_project_config()?;
+    let schema = Schema::load();
+    let templates = LabelTemplates::load()?;
+    let sheets = SheetConfig::load()?;
+    let sheet_name = match &args.sheet {
+        Some(name) => name.clone(),
+        None => sheets
+            .names()
+            .into_iter()
+            .next()
+            .ok_or("没有可用的标签纸")?,
+    };
+    let sheet = sheets
+        .get(&sheet_name)
+        .ok_or_else(|| format!("未知标签纸: {sheet_name}"))?;
+    let settings = config::load_export_config();
+    std::fs::create_dir_all(&args.out).map_err(|e| format!("创建输出目录失败: {e}"))?;
+
+    let mut manifest = Manifest::new(TOOL);
+    let mut used = HashSet::new();
+    let mut report = batch::Report::default();
+    let mut failed = 0;
+    for row in &rows {
+        let outcome = Form::from_row(row, &proj).and_then(|form| {
+            let (labels, _, type_label) = form.generate(&proj, &ks, &schema, &templates)?;
+            let pdf = safe_file_name(&format!(
+                "{:03}_{type_label}_{}.pdf",
+                row.line,
+                row.get(batch::COL_LOT)
+            ));
+            let count = labels.len();
+            let sections = [(type_label, labels)];
+            let mut written = Vec::new();
+            let issued = issue_batch(&sections, || {
+                written = stage_row(&args.out, |dir, staged| {
+                    let [(type_label, labels)] = &sections;
+                    let images: Vec<_> = labels.iter().map(|l| (l, type_label.as_str())).collect();
+                    write_images(
+                        &images,
+                        &args.name_template,
+                        dir,
+                        &settings,
+                        &mut used,
+                        staged,
+                    )?;
+                    write_pdf(
+                        &sections,
+                        (sheet_name.as_str(), sheet),
+                        0,
+                        (sheets.offset_x, sheets.offset_y),
+                        &dir.join(&pdf),
+                        staged,
+                    )
+                })?;
+                Ok(())
+            });
+            match issued {
+                Ok(()) => manifest.files.extend(written),
+                Err(e) => {
+                    for f in &written {
+                        let _ = std::fs::remove_file(args.out.join(&f.file));
+                    }
+                    return Err(e);
+                }
+            }
+            Ok(count)
+        });
+        match outcome {
+            Ok(count) => report.push(row, true, count, ""),
+            Err(e) => {
+                failed += 1;
+                report.push(row, false, 0, &e);
+            }
+        }
+    }
+    write_manifest(manifest, &folder_manifest_path(&args.out), &site_key)?;
+    report.save(&args.out.join(batch::REPORT_NAME))?;
+    Ok((rows.len(), failed))
+}
+
+/// Run `write` against an empty staging folder inside `out`, then move the
+/// files it added to its manifest into `out` and return their entries. A row
+/// that fails part-way leaves neither files nor manifest entries behind.
+fn stage_row(
+    out: &Path,
+    write: impl FnOnce(&Path, &mut Manifest) -> Result<(), String>,
+) -> Result<Vec<ManifestFile>, String> {
+    let staging = out.join(".staging");
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir_all(&staging).map_err(|e| format!("创建临时目录失败: {e}"))?;
+    let mut staged = Manifest::new(TOOL);
+    let result = write(&staging, &mut staged).and_then(|()| {
+        let mut moved = Vec::new();
+        for f in &staged.files {
+            let to = out.join(&f.file);
+            if let Err(e) = std::fs::rename(staging.join(&f.file), &to) {
+                for path in moved {
+                    let _ = std::fs::remove_file(path);
+                }
+                return Err(format!("移动 {} 失败: {e}", f.file));
+            }
+            moved.push(to);
+        }
+        Ok(())
+    });
+    let _ = std::fs::remove_dir_all(&staging);
+    result.map(|()| staged.files)
+}
+
+// ── Catalog management ───────────────────────────────────────────────
+
+/// Fill the project and consumable combo boxes with the active entries.
+fn show_catalog(w: &RLCLIAWindow, proj: &config::ProjectConfig) {
+    let active = proj.active();
+    let names: Vec<slint::SharedString> = active.iter().map(|p| p.name.as_str().into()).collect();
+    let ids: Vec<slint::SharedString> = active.iter().map(|p| p.id.as_str().into()).collect();
+    let consumables: Vec<slint::SharedString> = proj
+        .active_consumables()
+        .iter()
+        .map(|c| c.name.as_str().into())
+        .collect();
+    w.set_project_names(ModelRc::new(VecModel::from(names)));
+    w.set_project_ids(ModelRc::new(VecModel::from(ids)));
+    w.set_consumable_names(ModelRc::new(VecModel::from(consumables)));
+}
+
+/// Keep each form on the same project and consumable when the catalog is
+/// replaced; a retired one falls back to the first entry.
+fn reselect_projects(w: &RLCLIAWindow, old: &config::ProjectConfig, new: &config::ProjectConfig) {
+    let project = |index: i32| {
+        old.at(index)
+            .and_then(|p| new.active().iter().position(|q| q.name == p.name))
+            .unwrap_or(0) as i32
+    };
+    w.set_reagent_project_index(project(w.get_reagent_project_index()));
+    w.set_calib_project_index(project(w.get_calib_project_index()));
+    w.set_quality_project_index(project(w.get_quality_project_index()));
+    let consumable = old
+        .active_consumables()
+        .get(w.get_consumable_type_index() as usize)
+        .and_then(|c| {
+            new.active_consumables()
+                .iter()
+                .position(|d| d.name == c.name)
+        })
+        .unwrap_or(0);
+    w.set_consumable_type_index(consumable as i32);
+}
+
+/// Working copy of the catalog on the management page. The fields on the
+/// page edit entry `index` of the list picked by `kind`.
+struct CatalogEditor {
+    draft: config::ProjectConfig,
+    /// 0 projects, 1 consumables.
+    kind: i32,
+    index: usize,
+}
+
+impl CatalogEditor {
+    fn new(proj: &config::ProjectConfig) -> CatalogEditor {
+        CatalogEditor {
+            draft: proj.clone(),
+            kind: 0,
+            index: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        if self.kind == 0 {
+            self.draft.projects.len()
+        } else {
+            self.draft.consumables.len()
+        }
+    }
+
+    /// Show the list and the selected entry's fields.
+    fn show(&mut self, w: &RLCLIAWindow) {
+        self.index = self.index.min(self.len().saturating_sub(1));
+        let retired = |r: bool| if r { "（已停用）" } else { "" };
+        let items: Vec<slint::SharedString> = if self.kind == 0 {
+            self.draft
+                .projects
+                .iter()
+                .map(|p| format!("{}  {}{}", p.id, p.name, retired(p.retired)).into())
+                .collect()
+        } else {
+            self.draft
+                .consumables
+                .iter()
+                .map(|c| format!("{}  标签{:?}{}", c.name, c.label, retired(c.retired)).into())
+                .collect()
+        };
+        w.set_catalog_kind(self.kind);
+        w.set_catalog_items(ModelRc::new(VecModel::from(items)));
+        w.set_catalog_index(self.index as i32);
+        if self.kind == 0 {
+            let p = self
+                .draft
+                .projects
+                .get(self.index)
+                .cloned()
+                .unwrap_or_else(|| Project::new("", ""));
+            w.set_cat_id(p.id.into());
+            w.set_cat_ascii(self.draft.instrument_name(&p.name).into());
+            w.set_cat_name(p.name.into());
+            w.set_cat_units(p.units.join(", ").into());
+            w.set_cat_valid_days(p.valid_days.to_string().into());
+            w.set_cat_test_counts(p.test_counts.to_string().into());
+            w.set_cat_open_days(p.open_days.to_string().into());
+            w.set_cat_reaction_mode(p.reaction_mode.into());
+            let [a, b, c, d] = p.curve;
+            w.set_cat_curve_a(a.into());
+            w.set_cat_curve_b(b.into());
+            w.set_cat_curve_c(c.into());
+            w.set_cat_curve_d(d.into());
+            w.set_cat_retired(p.retired);
+        } else if let Some(c) = self.draft.consumables.get(self.index) {
+            w.set_cat_name(c.name.as_str().into());
+            w.set_cat_ascii(self.draft.instrument_name(&c.name).into());
+            w.set_cat_fluid_index(match c.label {
+                config::FluidLabel::A => 0,
+                config::FluidLabel::B => 1,
+            });
+            w.set_cat_retired(c.retired);
+        } else {
+            w.set_cat_name("".into());
+            w.set_cat_ascii("".into());
+            w.set_cat_retired(false);
+        }
+    }
+
+    /// Copy the page's fields into the selected entry. The instrument name
+    /// is kept in the transliteration table, and only when it differs.
+    fn apply(&mut self, w: &RLCLIAWindow) -> Result<(), String> {
+        let name = w.get_cat_name().trim().to_string();
+        let old = if self.kind == 0 {
+            self.draft.projects.get(self.index).map(|p| &p.name)
+        } else {
+            self.draft.consumables.get(self.index).map(|c| &c.name)
+        };
+        if let Some(old) = old.cloned() {
+            let ascii = w.get_cat_ascii().trim().to_string();
+            self.draft.transliterations.remove(&old);
+            if !ascii.is_empty() && ascii != name {
+                self.draft.transliterations.insert(name.clone(), ascii);
+            }
+        }
+        let number = |label: &str, v: slint::SharedString| {
+            v.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("{label}必须为非负整数"))
+        };
+        if self.kind == 0 {
+            let valid_days = number("有效天数", w.get_cat_valid_days())?;
+            let test_counts = number("测试/盒", w.get_cat_test_counts())?;
+            let open_days = number("开瓶天数", w.get_cat_open_days())?;
+            let Some(p) = self.draft.projects.get_mut(self.index) else {
+                return Ok(());
+            };
+            p.id = w.get_cat_id().trim().to_string();
+            p.name = name;
+            p.units = w
+                .get_cat_units()
+                .split([',', '，'])
+                .map(str::trim)
+                .filter(|u| !u.is_empty())
+                .map(str::to_string)
+                .collect();
+            p.valid_days = valid_days;
+            p.test_counts = test_counts;
+            p.open_days = open_days;
+            p.reaction_mode = w.get_cat_reaction_mode().trim().to_string();
+            p.curve = [
+                w.get_cat_curve_a().trim().to_string(),
+                w.get_cat_curve_b().trim().to_string(),
+                w.get_cat_curve_c().trim().to_string(),
+                w.get_cat_curve_d().trim().to_string(),
+            ];
+        } else if let Some(c) = self.draft.consumables.get_mut(self.index) {
+            c.name = name;
+            c.label = if w.get_cat_fluid_index() == 1 {
+                config::FluidLabel::B
+            } else {
+                config::FluidLabel::A
+            };
+        }
+        Ok(())
+    }
+
+    /// Append a new entry and select it; a project gets the next free numeric ID.
+    fn add(&mut self) {
+        if self.kind == 0 {
+            let next = self
+                .draft
+                .projects
+                .iter()
+                .filter_map(|p| p.id.parse::<u32>().ok())
+                .max()
+                .unwrap_or(0)
+                + 1;
+            self.draft
+                .projects
+                .push(Project::new(&next.to_string(), "新项目"));
+        } else {
+            self.draft.consumables.push(config::Consumable {
+                name: "新耗材".to_string(),
+                label: config::FluidLabel::A,
+                retired: false,
+            });
+        }
+        self.index = self.len() - 1;
+    }
+
+    /// Move the selected entry `delta` places up (negative) or down.
+    fn shift(&mut self, delta: i32) {
+        let to = self.index as i64 + delta as i64;
+        if to < 0 || to >= self.len() as i64 {
+            return;
+        }
+        let to = to as usize;
+        if self.kind == 0 {
+            self.draft.projects.swap(self.index, to);
+        } else {
+            self.draft.consumables.swap(self.index, to);
+        }
+        self.index = to;
+    }
+
+    fn toggle_retired(&mut self) {
+        if self.kind == 0 {
+            if let Some(p) = self.draft.projects.get_mut(self.index) {
+                p.retired = !p.retired;
+            }
+        } else if let Some(c) = self.draft.consumables.get_mut(self.index) {
+            c.retired = !c.retired;
+        }
+    }
+}
+
+// ── Entry point ──────────────────────────────────────────────────────
+
+/// The batch last previewed on each page, kept for export.
+#[derive(Clone, Default)]
+struct Batches(Arc<Mutex<HashMap<String, (Sections, bool)>>>);
+
+impl Batches {
+    fn set(&self, typ: &str, sections: Sections) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(typ.to_string(), (sections, false));
+    }
+
+    /// Sections of page `typ`, empty when nothing was previewed.
+    fn sections(&self, typ: &str) -> Sections {
+        self.0
+            .lock()
+            .unwrap()
+            .get(typ)
+            .map(|(sections, _)| sections.clone())
+            .unwrap_or_default()
+    }
+
+    /// Run `export` on page `typ`'s batch. The first export that succeeds
+    /// issues its serials (see [`issue_batch`]); exporting the same preview
+    /// again, e.g. as PDF after PNG, writes the same labels.
+    fn export<T>(
+        &self,
+        typ: &str,
+        export: impl FnOnce(&Sections) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let (sections, issued) = self.0.lock().unwrap().get(typ).cloned().unwrap_or_default();
+        if issued {
+            return export(&sections);
+        }
+        let out = issue_batch(&sections, || export(&sections))?;
+        if let Some(entry) = self.0.lock().unwrap().get_mut(typ) {
+            entry.1 = true;
+        }
+        Ok(out)
+    }
+
+    /// Every label of page `typ` in print order.
+    fn labels(&self, typ: &str) -> Vec<GeneratedLabel> {
+        self.sections(typ)
+            .into_iter()
+            .flat_map(|(_, labels)| labels)
+            .collect()
+    }
+}
+
+pub fn run() {
+    let (proj, proj_error) = match config::load_project_config() {
+        Ok(cfg) => (cfg, None),
+        Err(e) => (config::ProjectConfig::default(), Some(e)),
+    };
+    let schema = Arc::new(Schema::load());
+    let mut load_errors = Vec::new();
+    let templates = Arc::new(LabelTemplates::load().unwrap_or_else(|e| {
+        load_errors.push(format!("{e}，已改用内置标签模板"));
+        LabelTemplates::default()
+    }));
+    // While sheets.json is unreadable the offsets are used but not saved over it.
+    let (sheets, sheets_broken) = match SheetConfig::load() {
+        Ok(cfg) => (cfg, false),
+        Err(e) => {
+            load_errors.push(format!("{e}，已改用内置标签纸且不保存打印偏移"));
+            (SheetConfig::builtin(), true)
+        }
+    };
+    let sheets = Arc::new(Mutex::new(sheets));
+    let window = RLCLIAWindow::new().expect("创建窗口失败");
+
+    // Populate project combos
+    show_catalog(&window, &proj);
+    if let Some(e) = &proj_error {
+        window.set_catalog_error(format!("{e}。请修正该文件，或备份并丢弃后再保存").into());
+        window.set_catalog_broken(true);
+        load_errors.insert(0, format!("{e}，已改用内置项目"));
+    }
+    if !load_errors.is_empty() {
+        window.set_status(load_errors.join("；").into());
+    }
+
+    // PDF sheet stock and printer offset
+    {
+        let cfg = sheets.lock().unwrap();
+        let names: Vec<slint::SharedString> =
+            cfg.names().iter().map(|s| s.as_str().into()).collect();
+        window.set_sheet_names(ModelRc::new(VecModel::from(names)));
+        window.set_sheet_offset_x(cfg.offset_x.to_string().into());
+        window.set_sheet_offset_y(cfg.offset_y.to_string().into());
+    }
+    {
+        let cfg = config::load_export_config();
+        let dpi = PRINTER_DPIS.iter().position(|d| *d == cfg.dpi).unwrap_or(1);
+        window.set_dpi_index(dpi as i32);
+        let formats: Vec<slint::SharedString> =
+            EXPORT_FORMATS.iter().map(|s| (*s).into()).collect();
+        window.set_export_formats(ModelRc::new(VecModel::from(formats)));
+        window.set_export_format_index(cfg.format_index as i32);
+        window.set_mono_threshold(cfg.threshold.to_string().into());
+        window.set_mono_dither(cfg.dither);
+    }
 
     // Default dates
     let today = today_str();
@@ -279,101 +1641,142 @@ pub fn run() {
     window.set_calib_prod_date(today.clone().into());
     window.set_consumable_prod_date(today.clone().into());
     window.set_quality_prod_date(today.clone().into());
+    if let Some(first) = proj.at(0) {
+        for typ in ["reagent", "calibration", "quality"] {
+            prefill(&window, typ, first);
+        }
+    }
 
-    // Per-type image storage
-    let imgs_reagent: Arc<Mutex<Vec<image::GrayImage>>> = Arc::default();
-    let imgs_calib: Arc<Mutex<Vec<image::GrayImage>>> = Arc::default();
-    let imgs_cons: Arc<Mutex<Vec<image::GrayImage>>> = Arc::default();
-    let imgs_qual: Arc<Mutex<Vec<image::GrayImage>>> = Arc::default();
+    let proj = Arc::new(Mutex::new(proj));
+    // Set while the on-disk project.json is unreadable, so saving cannot overwrite it.
+    let proj_error = Arc::new(Mutex::new(proj_error));
+
+    // Per-page image storage
+    let batches = Batches::default();
+    let keystore: Arc<Mutex<Keystore>> = Arc::default();
+    window.set_active_key_id(keystore.lock().unwrap().active().id.clone().into());
+    let site_key: Arc<Mutex<Option<SiteKey>>> = Arc::default();
+    window.set_site_key_state(site_key_state(None).into());
+
+    // ── Project master data ───────────────────────────────────────────
+    {
+        let w = window.as_weak();
+        let p = proj.clone();
+        window.on_project_selected(move |etype, index| {
+            if let Some(project) = p.lock().unwrap().at(index) {
+                prefill(&w.unwrap(), &etype, project);
+            }
+        });
+    }
 
     // ── Expiry computation ────────────────────────────────────────────
-    window.on_compute_expiry(|pd, vd| {
-        compute_expire(&pd.to_string(), vd.to_string().parse().unwrap_or(365)).into()
-    });
+    window.on_compute_expiry(|pd, vd| expire_text(pd.trim(), vd.trim()).unwrap_or_default().into());
 
     // ── Generate preview ──────────────────────────────────────────────
     {
         let w = window.as_weak();
         let p = proj.clone();
-        let ir = imgs_reagent.clone();
-        let ic = imgs_calib.clone();
-        let ico = imgs_cons.clone();
-        let iq = imgs_qual.clone();
+        let bs = batches.clone();
+        let ks = keystore.clone();
+        let sc = schema.clone();
+        let tp = templates.clone();
         window.on_generate_preview(move |etype| {
             let w = w.unwrap();
             let typ = etype.to_string();
-            match dispatch_generate(&typ, &w, &p) {
-                Ok((imgs, _, label)) => {
+            let ks = ks.lock().unwrap();
+            let p = p.lock().unwrap();
+            let sections = if typ == "kit" {
+                KitForm::from_window(&w, &p).generate(&p, &ks, &sc, &tp)
+            } else {
+                Form::from_window(&typ, &w, &p)
+                    .and_then(|form| form.generate(&p, &ks, &sc, &tp))
+                    .map(|(imgs, _, label)| vec![(label, imgs)])
+            };
+            match sections {
+                Ok(sections) => {
+                    let imgs: Vec<GeneratedLabel> =
+                        sections.iter().flat_map(|(_, l)| l.clone()).collect();
                     if imgs.is_empty() {
                         w.set_status("没有图像".into());
                         return;
                     }
-                    let preview = gray_to_slint_image(&imgs[0]);
-                    match typ.as_str() {
-                        "reagent" => {
-                            w.set_preview_reagent(preview);
-                            w.set_has_preview_reagent(true);
-                            *ir.lock().unwrap() = imgs;
-                        }
-                        "calibration" => {
-                            w.set_preview_calibration(preview);
-                            w.set_has_preview_calibration(true);
-                            *ic.lock().unwrap() = imgs;
+                    show_gallery(&w, &imgs);
+                    let status = match sections.as_slice() {
+                        [(label, imgs)] => format!("{label} 预览已生成，共 {} 张", imgs.len()),
+                        _ => {
+                            let counts: Vec<String> = sections
+                                .iter()
+                                .map(|(label, imgs)| format!("{label} {} 张", imgs.len()))
+                                .collect();
+                            format!("套装预览已生成：{}", counts.join("，"))
                         }
-                        "consumable" => {
-                            w.set_preview_consumable(preview);
-                            w.set_has_preview_consumable(true);
-                            *ico.lock().unwrap() = imgs;
-                        }
-                        "quality" => {
-                            w.set_preview_quality(preview);
-                            w.set_has_preview_quality(true);
-                            *iq.lock().unwrap() = imgs;
-                        }
-                        _ => {}
-                    }
-                    w.set_status(format!("{label} 预览已生成").into());
+                    };
+                    bs.set(&typ, sections);
+                    w.set_status(status.into());
                     w.set_toast_msg("预览成功".into());
                     w.set_toast_visible(true);
                 }
-                Err(e) => w.set_status(format!("错误: {e}").into()),
+                Err(e) => {
+                    // Drop the previous batch so a failed check cannot be
+                    // followed by exporting stale labels.
+                    bs.set(&typ, Vec::new());
+                    show_gallery(&w, &[]);
+                    w.set_status(format!("错误: {e}").into());
+                }
             }
         });
     }
 
+    // ── Switch gallery with the nav page ─────────────────────────────
+    {
+        let w = window.as_weak();
+        let bs = batches.clone();
+        window.on_show_gallery(move |etype| {
+            let w = w.unwrap();
+            show_gallery(&w, &bs.labels(&etype));
+        });
+    }
+
     // ── Export PNG ─────────────────────────────────────────────────────
     {
         let w = window.as_weak();
-        let ir = imgs_reagent.clone();
-        let ic = imgs_calib.clone();
-        let ico = imgs_cons.clone();
-        let iq = imgs_qual.clone();
+        let bs = batches.clone();
+        let sk = site_key.clone();
         window.on_export_png(move |etype| {
             let w = w.unwrap();
             let typ = etype.to_string();
-            let imgs = match typ.as_str() {
-                "reagent" => ir.lock().unwrap().clone(),
-                "calibration" => ic.lock().unwrap().clone(),
-                "consumable" => ico.lock().unwrap().clone(),
-                "quality" => iq.lock().unwrap().clone(),
-                _ => {
-                    w.set_status("请先生成预览".into());
+            let sections = bs.sections(&typ);
+            if sections.iter().all(|(_, l)| l.is_empty()) {
+                w.set_status("请先点击「生成预览」".into());
+                return;
+            }
+            let template = w.get_png_name_template().to_string();
+            let settings = match export_settings(&w) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    w.set_status(e.into());
                     return;
                 }
             };
-            if imgs.is_empty() {
-                w.set_status("请先点击「生成预览」".into());
+            let key = sk.lock().unwrap();
+            let Some(key) = key.as_ref() else {
+                w.set_status(SITE_KEY_LOCKED.into());
                 return;
-            }
-            if let Some(path) = rfd::FileDialog::new()
-                .set_title("保存PNG图片")
-                .set_file_name(&format!("{}.png", label_of(&typ)))
-                .add_filter("PNG图片", &["png"])
-                .save_file()
+            };
+            if let Some(dir) = rfd::FileDialog::new()
+                .set_title("选择图片导出文件夹")
+                .pick_folder()
             {
-                match imgs[0].save(&path) {
-                    Ok(_) => {
-                        w.set_status(format!("已保存: {}", path.display()).into());
+                let result = bs.export(&typ, |sections| {
+                    let batch: Vec<_> = sections
+                        .iter()
+                        .flat_map(|(label, imgs)| imgs.iter().map(move |l| (l, label.as_str())))
+                        .collect();
+                    export_png_batch(&batch, &template, &dir, &settings, key)
+                });
+                match result {
+                    Ok(count) => {
+                        w.set_status(format!("已导出 {count} 个文件到: {}", dir.display()).into());
                         w.set_toast_msg("导出成功".into());
                         w.set_toast_visible(true);
                     }
@@ -386,34 +1789,72 @@ pub fn run() {
     // ── Export PDF ─────────────────────────────────────────────────────
     {
         let w = window.as_weak();
-        let ir = imgs_reagent.clone();
-        let ic = imgs_calib.clone();
-        let ico = imgs_cons.clone();
-        let iq = imgs_qual.clone();
+        let bs = batches.clone();
+        let sh = sheets.clone();
+        let sk = site_key.clone();
         window.on_export_pdf(move |etype| {
             let w = w.unwrap();
             let typ = etype.to_string();
-            let imgs = match typ.as_str() {
-                "reagent" => ir.lock().unwrap().clone(),
-                "calibration" => ic.lock().unwrap().clone(),
-                "consumable" => ico.lock().unwrap().clone(),
-                "quality" => iq.lock().unwrap().clone(),
-                _ => {
-                    w.set_status("请先生成预览".into());
-                    return;
-                }
-            };
-            if imgs.is_empty() {
+            let sections = bs.sections(&typ);
+            if sections.iter().all(|(_, l)| l.is_empty()) {
                 w.set_status("请先点击「生成预览」".into());
                 return;
             }
+            let key = sk.lock().unwrap();
+            let Some(key) = key.as_ref() else {
+                w.set_status(SITE_KEY_LOCKED.into());
+                return;
+            };
+            let mut cfg = sh.lock().unwrap();
+            let names = cfg.names();
+            let Some(sheet) = names
+                .get(w.get_sheet_index() as usize)
+                .and_then(|n| cfg.get(n))
+                .cloned()
+            else {
+                w.set_status("请选择标签纸".into());
+                return;
+            };
+            let Ok(skip) = w.get_sheet_skip().trim().parse::<usize>() else {
+                w.set_status("跳过格数必须为非负整数".into());
+                return;
+            };
+            let (Ok(ox), Ok(oy)) = (
+                w.get_sheet_offset_x().trim().parse::<f32>(),
+                w.get_sheet_offset_y().trim().parse::<f32>(),
+            ) else {
+                w.set_status("打印偏移必须为数字（毫米）".into());
+                return;
+            };
+            if (ox, oy) != (cfg.offset_x, cfg.offset_y) {
+                cfg.offset_x = ox;
+                cfg.offset_y = oy;
+                // An unreadable sheets.json is left for the user to fix.
+                if !sheets_broken && let Err(e) = cfg.save() {
+                    w.set_status(e.into());
+                    return;
+                }
+            }
+            drop(cfg);
             if let Some(path) = rfd::FileDialog::new()
                 .set_title("保存PDF")
                 .set_file_name(&format!("{}.pdf", label_of(&typ)))
                 .add_filter("PDF文件", &["pdf"])
                 .save_file()
             {
-                match generate_pdf(&imgs, path.to_str().unwrap_or("")) {
+                let result = bs.export(&typ, |sections| {
+                    let mut manifest = Manifest::new(TOOL);
+                    write_pdf(
+                        sections,
+                        (names[w.get_sheet_index() as usize].as_str(), &sheet),
+                        skip,
+                        (ox, oy),
+                        &path,
+                        &mut manifest,
+                    )?;
+                    write_manifest(manifest, &manifest_path_for(&path), key)
+                });
+                match result {
                     Ok(_) => {
                         w.set_status(format!("已保存: {}", path.display()).into());
                         w.set_toast_msg("导出成功".into());
@@ -425,18 +1866,475 @@ pub fn run() {
         });
     }
 
+    // ── Export ZPL ─────────────────────────────────────────────────────
+    {
+        let w = window.as_weak();
+        let bs = batches.clone();
+        let sk = site_key.clone();
+        window.on_export_zpl(move |etype| {
+            let w = w.unwrap();
+            let typ = etype.to_string();
+            let imgs = bs.labels(&typ);
+            if imgs.is_empty() {
+                w.set_status("请先点击「生成预览」".into());
+                return;
+            }
+            let settings = match export_settings(&w) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    w.set_status(e.into());
+                    return;
+                }
+            };
+            let key = sk.lock().unwrap();
+            let Some(key) = key.as_ref() else {
+                w.set_status(SITE_KEY_LOCKED.into());
+                return;
+            };
+            // ^GF is always 1-bit; binarize with the chosen threshold / dither,
+            // and scan-check those bits when they are what gets printed.
+            let graphic = w.get_zpl_graphic();
+            let laid_out = match imgs
+                .iter()
+                .map(|l| {
+                    let (t, img) = l.at_dpi(settings.dpi)?;
+                    let mono = to_mono(&img, &settings.mono_options());
+                    if graphic {
+                        verify_scan(&mono, &l.content).map_err(|e| format!("1位图像: {e}"))?;
+                    }
+                    Ok::<_, String>((t, mono))
+                })
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    w.set_status(format!("错误: {e}").into());
+                    return;
+                }
+            };
+            if let Some(path) = rfd::FileDialog::new()
+                .set_title("保存ZPL")
+                .set_file_name(&format!("{}.zpl", label_of(&typ)))
+                .add_filter("ZPL文件", &["zpl"])
+                .save_file()
+            {
+                let labels: Vec<VectorLabel> = imgs
+                    .iter()
+                    .zip(&laid_out)
+                    .map(|(l, (template, image))| VectorLabel {
+                        template,
+                        content: &l.content,
+                        modules: &l.modules,
+                        texts: &l.texts,
+                        image,
+                    })
+                    .collect();
+                let zpl = generate_zpl(&labels, graphic, w.get_zpl_font().trim());
+                let result = bs.export(&typ, |sections| {
+                    let mut manifest = Manifest::new(TOOL);
+                    write_zpl(sections, &zpl, (graphic, &settings), &path, &mut manifest)?;
+                    write_manifest(manifest, &manifest_path_for(&path), key)
+                });
+                match result {
+                    Ok(_) => {
+                        w.set_status(format!("已保存: {}", path.display()).into());
+                        w.set_toast_msg("导出成功".into());
+                        w.set_toast_visible(true);
+                    }
+                    Err(e) => w.set_status(format!("ZPL保存失败: {e}").into()),
+                }
+            }
+        });
+    }
+
+    // ── Keystore ──────────────────────────────────────────────────────
+    // One passphrase unlocks both the payload keystore and the site key that
+    // signs export manifests; either may not have been created yet.
+    {
+        let w = window.as_weak();
+        let ks = keystore.clone();
+        let sk = site_key.clone();
+        window.on_unlock_keystore(move || {
+            let w = w.unwrap();
+            let pass = w.get_keystore_passphrase().to_string();
+            let mut unlocked = Vec::new();
+            if Path::new(KEYSTORE_PATH).exists() {
+                match Keystore::load(KEYSTORE_PATH, &pass) {
+                    Ok(loaded) => {
+                        w.set_active_key_id(loaded.active().id.clone().into());
+                        *ks.lock().unwrap() = loaded;
+                        unlocked.push("密钥库");
+                    }
+                    Err(e) => {
+                        w.set_status(format!("错误: {e}").into());
+                        return;
+                    }
+                }
+            }
+            if Path::new(SITE_KEY_PATH).exists() {
+                match SiteKey::load(SITE_KEY_PATH, &pass) {
+                    Ok(key) => {
+                        w.set_site_key_state(site_key_state(Some(&key)).into());
+                        *sk.lock().unwrap() = Some(key);
+                        unlocked.push("签名密钥");
+                    }
+                    Err(e) => {
+                        w.set_status(format!("错误: {e}").into());
+                        return;
+                    }
+                }
+            }
+            if unlocked.is_empty() {
+                w.set_status("尚未创建密钥库或签名密钥".into());
+                return;
+            }
+            w.set_toast_msg(format!("{}已解锁", unlocked.join("和")).into());
+            w.set_toast_visible(true);
+        });
+    }
+    {
+        let w = window.as_weak();
+        let sk = site_key.clone();
+        window.on_create_site_key(move || {
+            let w = w.unwrap();
+            let pass = w.get_keystore_passphrase().to_string();
+            match SiteKey::create(SITE_KEY_PATH, &pass) {
+                Ok(key) => {
+                    w.set_site_key_state(site_key_state(Some(&key)).into());
+                    *sk.lock().unwrap() = Some(key);
+                    w.set_status(
+                        format!(
+                            "已创建签名密钥，请把 {} 分发给校验方",
+                            manifest::TRUSTED_KEYS_PATH
+                        )
+                        .into(),
+                    );
+                    w.set_toast_msg("已创建签名密钥".into());
+                    w.set_toast_visible(true);
+                }
+                Err(e) => w.set_status(format!("错误: {e}").into()),
+            }
+        });
+    }
+    {
+        let w = window.as_weak();
+        let ks = keystore.clone();
+        window.on_rotate_key(move || {
+            let w = w.unwrap();
+            let pass = w.get_keystore_passphrase().to_string();
+            // Re-open the existing store first so no previously issued key is lost.
+            let mut next = if std::path::Path::new(KEYSTORE_PATH).exists() {
+                match Keystore::load(KEYSTORE_PATH, &pass) {
+                    Ok(k) => k,
+                    Err(e) => {
+                        w.set_status(format!("错误: {e}").into());
+                        return;
+                    }
+                }
+            } else {
+                Keystore::default()
+            };
+            let id = next.rotate();
+            match next.save(KEYSTORE_PATH, &pass) {
+                Ok(_) => {
+                    w.set_active_key_id(id.into());
+                    *ks.lock().unwrap() = next;
+                    w.set_toast_msg("已生成新密钥".into());
+                    w.set_toast_visible(true);
+                }
+                Err(e) => w.set_status(format!("错误: {e}").into()),
+            }
+        });
+    }
+
     // ── Decrypt ───────────────────────────────────────────────────────
     {
         let w = window.as_weak();
+        let ks = keystore.clone();
+        let p = proj.clone();
         window.on_decrypt_data(move || {
             let w = w.unwrap();
             let input = w.get_decrypt_input().to_string();
-            match encryptor::decrypt(&input) {
-                Ok(plain) => w.set_decrypt_output(plain.into()),
-                Err(e) => w.set_decrypt_output(format!("错误: {e}").into()),
+            let result = encryptor::decrypt(&ks.lock().unwrap(), &input);
+            let fields = match result {
+                Ok(plain) => {
+                    let fields = encryptor::parse(&p.lock().unwrap(), &plain)
+                        .map(|p| p.fields())
+                        .unwrap_or_default();
+                    w.set_decrypt_output(plain.into());
+                    fields
+                }
+                Err(e) => {
+                    w.set_decrypt_output(format!("错误: {e}").into());
+                    Vec::new()
+                }
+            };
+            let names: Vec<slint::SharedString> = fields.iter().map(|(k, _)| (*k).into()).collect();
+            let values: Vec<slint::SharedString> =
+                fields.iter().map(|(_, v)| v.as_str().into()).collect();
+            w.set_decrypt_field_names(ModelRc::new(VecModel::from(names)));
+            w.set_decrypt_field_values(ModelRc::new(VecModel::from(values)));
+        });
+    }
+
+    // ── History ───────────────────────────────────────────────────────
+    let history: Arc<Mutex<Vec<AuditRecord>>> = Arc::default();
+    {
+        let w = window.as_weak();
+        let h = history.clone();
+        window.on_search_history(move |query| {
+            let w = w.unwrap();
+            match AuditLog::default().search(&query) {
+                Ok(records) => {
+                    show_history(&w, &records);
+                    w.set_status(format!("找到 {} 条记录", records.len()).into());
+                    *h.lock().unwrap() = records;
+                }
+                Err(e) => w.set_status(e.into()),
             }
         });
     }
+    {
+        let w = window.as_weak();
+        let h = history.clone();
+        let ks = keystore.clone();
+        let p = proj.clone();
+        let tp = templates.clone();
+        let sk = site_key.clone();
+        window.on_reexport_history(move || {
+            let w = w.unwrap();
+            let records: Vec<AuditRecord> = h
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|r| r.tool == TOOL)
+                .cloned()
+                .collect();
+            if records.is_empty() {
+                w.set_status("没有可重新导出的 RL-CLIA 记录".into());
+                return;
+            }
+            let settings = match export_settings(&w) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    w.set_status(e.into());
+                    return;
+                }
+            };
+            let labels = {
+                let ks = ks.lock().unwrap();
+                let p = p.lock().unwrap();
+                records
+                    .iter()
+                    .map(|r| {
+                        relabel(&ks, &p, &tp, r).map_err(|e| format!("{} {}: {e}", r.lot, r.serial))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            let labels = match labels {
+                Ok(labels) => labels,
+                Err(e) => {
+                    w.set_status(format!("重新生成失败: {e}").into());
+                    return;
+                }
+            };
+            let template = w.get_png_name_template().to_string();
+            let key = sk.lock().unwrap();
+            let Some(key) = key.as_ref() else {
+                w.set_status(SITE_KEY_LOCKED.into());
+                return;
+            };
+            if let Some(dir) = rfd::FileDialog::new()
+                .set_title("选择图片导出文件夹")
+                .pick_folder()
+            {
+                let batch: Vec<_> = labels
+                    .iter()
+                    .zip(&records)
+                    .map(|(l, r)| (l, r.label_type.as_str()))
+                    .collect();
+                match export_png_batch(&batch, &template, &dir, &settings, key) {
+                    Ok(count) => {
+                        w.set_status(
+                            format!("已重新导出 {count} 个文件到: {}", dir.display()).into(),
+                        );
+                        w.set_toast_msg("导出成功".into());
+                        w.set_toast_visible(true);
+                    }
+                    Err(e) => w.set_status(format!("保存失败: {e}").into()),
+                }
+            }
+        });
+    }
+
+    {
+        let w = window.as_weak();
+        window.on_verify_history(move || {
+            let w = w.unwrap();
+            match AuditLog::default().verify() {
+                Ok(count) => w.set_status(format!("审计记录完整，共 {count} 条").into()),
+                Err(e) => w.set_status(format!("审计记录校验失败: {e}").into()),
+            }
+        });
+    }
+
+    // ── Manifest verification ─────────────────────────────────────────
+    {
+        let w = window.as_weak();
+        window.on_verify_manifest(move || {
+            let w = w.unwrap();
+            if let Some(path) = rfd::FileDialog::new()
+                .set_title("选择导出清单")
+                .add_filter("清单文件", &["json"])
+                .pick_file()
+            {
+                match manifest::verify_manifest(&path) {
+                    Ok(count) => {
+                        w.set_status(format!("清单签名有效，{count} 个文件全部一致").into())
+                    }
+                    Err(e) => w.set_status(format!("清单校验失败: {e}").into()),
+                }
+            }
+        });
+    }
+
+    // ── Catalog management ────────────────────────────────────────────
+    {
+        let editor = Arc::new(Mutex::new(CatalogEditor::new(&proj.lock().unwrap())));
+        {
+            let w = window.as_weak();
+            let p = proj.clone();
+            let ed = editor.clone();
+            let broken = proj_error.clone();
+            window.on_catalog_open(move || {
+                let w = w.unwrap();
+                let mut broken = broken.lock().unwrap();
+                if broken.is_some() {
+                    // Pick up a project.json fixed outside the program.
+                    match config::load_project_config() {
+                        Ok(cfg) => {
+                            let mut p = p.lock().unwrap();
+                            reselect_projects(&w, &p, &cfg);
+                            *p = cfg;
+                            show_catalog(&w, &p);
+                            *broken = None;
+                            w.set_catalog_broken(false);
+                            w.set_catalog_error("".into());
+                            w.set_status("项目配置已重新读取".into());
+                        }
+                        Err(e) => w.set_catalog_error(
+                            format!("{e}。请修正该文件，或备份并丢弃后再保存").into(),
+                        ),
+                    }
+                }
+                let mut ed = ed.lock().unwrap();
+                *ed = CatalogEditor::new(&p.lock().unwrap());
+                ed.show(&w);
+            });
+        }
+        {
+            let w = window.as_weak();
+            let ed = editor.clone();
+            window.on_catalog_select(move |kind, index| {
+                let w = w.unwrap();
+                let mut ed = ed.lock().unwrap();
+                if let Err(e) = ed.apply(&w) {
+                    w.set_status(e.into());
+                    ed.show(&w);
+                    return;
+                }
+                ed.kind = kind;
+                ed.index = index.max(0) as usize;
+                ed.show(&w);
+            });
+        }
+        {
+            let w = window.as_weak();
+            let ed = editor.clone();
+            window.on_catalog_add(move || {
+                let w = w.unwrap();
+                let mut ed = ed.lock().unwrap();
+                match ed.apply(&w) {
+                    Ok(()) => ed.add(),
+                    Err(e) => w.set_status(e.into()),
+                }
+                ed.show(&w);
+            });
+        }
+        {
+            let w = window.as_weak();
+            let ed = editor.clone();
+            window.on_catalog_move(move |delta| {
+                let w = w.unwrap();
+                let mut ed = ed.lock().unwrap();
+                match ed.apply(&w) {
+                    Ok(()) => ed.shift(delta),
+                    Err(e) => w.set_status(e.into()),
+                }
+                ed.show(&w);
+            });
+        }
+        {
+            let w = window.as_weak();
+            let ed = editor.clone();
+            window.on_catalog_retire(move || {
+                let w = w.unwrap();
+                let mut ed = ed.lock().unwrap();
+                match ed.apply(&w) {
+                    Ok(()) => ed.toggle_retired(),
+                    Err(e) => w.set_status(e.into()),
+                }
+                ed.show(&w);
+            });
+        }
+        {
+            let w = window.as_weak();
+            let p = proj.clone();
+            let ed = editor.clone();
+            let broken = proj_error.clone();
+            window.on_catalog_save(move || {
+                let w = w.unwrap();
+                if let Some(e) = broken.lock().unwrap().as_ref() {
+                    w.set_status(format!("保存失败: {e}").into());
+                    return;
+                }
+                let mut ed = ed.lock().unwrap();
+                let result = ed.apply(&w).and_then(|_| ed.draft.save());
+                ed.show(&w);
+                match result {
+                    Ok(()) => {
+                        let mut p = p.lock().unwrap();
+                        reselect_projects(&w, &p, &ed.draft);
+                        *p = ed.draft.clone();
+                        show_catalog(&w, &p);
+                        w.set_catalog_error("".into());
+                        w.set_status("项目配置已保存".into());
+                        w.set_toast_msg("保存成功".into());
+                        w.set_toast_visible(true);
+                    }
+                    Err(e) => w.set_status(format!("保存失败: {e}").into()),
+                }
+            });
+        }
+        {
+            let w = window.as_weak();
+            let broken = proj_error.clone();
+            window.on_catalog_discard(move || {
+                let w = w.unwrap();
+                match config::discard_project_config() {
+                    Ok(backup) => {
+                        *broken.lock().unwrap() = None;
+                        w.set_catalog_broken(false);
+                        w.set_catalog_error("".into());
+                        w.set_status(
+                            format!("原项目配置已备份为 {backup}，保存后写入新文件").into(),
+                        );
+                    }
+                    Err(e) => w.set_status(e.into()),
+                }
+            });
+        }
+    }
 
     window.run().expect("运行失败");
 }
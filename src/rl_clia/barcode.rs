@@ -1,17 +1,10 @@
+use crate::font;
+use crate::sheet::{SheetTemplate, PT_PER_MM};
+use crate::template::{Align, LabelTemplate};
 use ab_glyph::{Font, ScaleFont};
 use image::GrayImage;
 use zxingcpp::*;
 
-const BARCODE_W: u32 = 600;
-const BARCODE_H: u32 = 300;
-pub const LABEL_W: u32 = 660;
-pub const LABEL_H: u32 = 580;
-
-struct LabelLayout {
-    barcode_y: u32,
-    footer_y: i32,
-}
-
 #[derive(Debug, Clone, Copy)]
 pub enum ImageType {
     ReagentInformation,
@@ -33,187 +26,90 @@ pub fn gray_to_slint_image(gray: &GrayImage) -> slint::Image {
     slint::Image::from_rgba8(buffer)
 }
 
-pub fn generate_barcode(data: &str) -> Result<GrayImage, String> {
-    let barcode = create(BarcodeFormat::PDF417)
+fn encode(data: &str) -> Result<Barcode, String> {
+    create(BarcodeFormat::PDF417)
         .options("columns:3,eclevel:0")
         .from_str(data)
-        .map_err(|e| format!("PDF417编码失败: {e}"))?;
-    let img = barcode
+        .map_err(|e| format!("PDF417编码失败: {e}"))
+}
+
+/// The PDF417 symbol at one pixel per module, for drawing the modules as
+/// vector rectangles and for enlarging by whole factors in raster labels.
+pub fn generate_modules(data: &str) -> Result<GrayImage, String> {
+    let img = encode(data)?
         .to_image_with(
             &write()
-                .scale(3)
+                .scale(1)
                 .add_quiet_zones(true)
                 .add_hrt(false)
                 .rotate(0),
         )
         .map_err(|e| format!("条码图像生成失败: {e}"))?;
-    let mut gray = GrayImage::from(&img);
-    if gray.width() != BARCODE_W || gray.height() != BARCODE_H {
-        gray = image::imageops::resize(
-            &gray,
-            BARCODE_W,
-            BARCODE_H,
-            image::imageops::FilterType::Nearest,
-        );
-    }
-    Ok(gray)
-}
-
-pub fn draw_barcode_with_text(
-    barcode: &GrayImage,
-    image_type: ImageType,
-    project_name: &str,
-    lot_number: &str,
-    prod_date: &str,
-    expire_date: &str,
-    test_counts: &str,
-) -> GrayImage {
-    let mut canvas = GrayImage::from_pixel(LABEL_W, LABEL_H, image::Luma([255]));
-    let layout = layout_for(image_type);
-    // Paste barcode centered horizontally at the per-label vertical position.
-    let bx = (LABEL_W - BARCODE_W) / 2;
-    let by = layout.barcode_y;
-    for y in 0..barcode.height().min(LABEL_H.saturating_sub(by)) {
-        for x in 0..barcode.width().min(LABEL_W.saturating_sub(bx)) {
-            canvas.put_pixel(bx + x, by + y, *barcode.get_pixel(x, y));
-        }
-    }
-    if let Some(font) = load_font() {
-        render_labels(
-            &mut canvas,
-            &font,
-            image_type,
-            project_name,
-            lot_number,
-            prod_date,
-            expire_date,
-            test_counts,
-            &layout,
-        );
-    }
-    canvas
+    Ok(GrayImage::from(&img))
 }
 
-fn layout_for(image_type: ImageType) -> LabelLayout {
-    let barcode_y = match image_type {
-        ImageType::ReagentInformation => 130,
-        ImageType::QualityControl | ImageType::CalibrationProduct => 98,
-        ImageType::ExcitationFluidA | ImageType::ExcitationFluidB => 82,
-    };
-    LabelLayout {
-        barcode_y,
-        footer_y: (barcode_y + BARCODE_H + 20) as i32,
+/// Read `image` back with the zxing reader and check it decodes to exactly
+/// `content`, byte for byte. Returns the decoded text.
+pub fn verify_scan(image: &GrayImage, content: &str) -> Result<String, String> {
+    let found = read()
+        .formats(BarcodeFormat::PDF417)
+        .try_harder(true)
+        .from(image)
+        .map_err(|e| format!("扫码校验失败: {e}"))?;
+    let barcode = found.first().ok_or("扫码校验失败: 图像中未识别到条码")?;
+    if barcode.bytes() != content.as_bytes() {
+        return Err(format!(
+            "扫码校验失败: 识别内容与源内容不一致\n源内容: {content}\n识别结果: {}",
+            barcode.text()
+        ));
     }
+    Ok(barcode.text())
 }
 
-fn load_font() -> Option<ab_glyph::FontArc> {
-    // Try TTF files first (ab_glyph handles single TTF better than TTC)
-    let candidates: &[&str] = if cfg!(target_os = "windows") {
-        &[
-            "C:/Windows/Fonts/simhei.ttf",
-            "C:/Windows/Fonts/msyh.ttf",
-            "C:/Windows/Fonts/arial.ttf",
-            "C:/Windows/Fonts/times.ttf",
-        ]
-    } else if cfg!(target_os = "macos") {
-        &[
-            "/System/Library/Fonts/STHeiti Light.ttc",
-            "/System/Library/Fonts/Helvetica.ttc",
-        ]
-    } else {
-        &["/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf"]
-    };
-    for path in candidates {
-        if let Ok(data) = std::fs::read(path) {
-            if let Ok(font) = ab_glyph::FontArc::try_from_vec(data) {
-                return Some(font);
-            }
+/// Render a label from `template`, filling its `{field}` placeholders from `fields`.
+///
+/// `modules` is the symbol from [`generate_modules`]. It is enlarged by the
+/// largest whole factors that fit the template's barcode box, separately
+/// across and down, so every module and every row keeps the same size; the
+/// symbol is centred and the rest of the box stays white.
+///
+/// Fails if no available font can draw some line of text.
+pub fn draw_barcode_with_text(
+    modules: &GrayImage,
+    template: &LabelTemplate,
+    fields: &[(&str, &str)],
+) -> Result<GrayImage, String> {
+    let mut canvas = GrayImage::from_pixel(template.width, template.height, image::Luma([255]));
+    let b = &template.barcode;
+    let (w, h) = modules.dimensions();
+    let (mx, my) = ((b.width / w).max(1), (b.height / h).max(1));
+    let barcode = GrayImage::from_fn(w * mx, h * my, |x, y| *modules.get_pixel(x / mx, y / my));
+    let x = b.x + b.width.saturating_sub(barcode.width()) / 2;
+    let y = b.y + b.height.saturating_sub(barcode.height()) / 2;
+    image::imageops::overlay(&mut canvas, &barcode, x as i64, y as i64);
+    for t in &template.texts {
+        let text = t.render(fields);
+        let font = font::font_for(&text)?;
+        if t.bold {
+            draw_text_bold(&mut canvas, &font.font, t.size, &text, t.x, t.y, t.align);
+        } else {
+            draw_text(&mut canvas, &font.font, t.size, &text, t.x, t.y, t.align);
         }
     }
-    None
-}
-
-fn render_labels(
-    canvas: &mut GrayImage,
-    font: &ab_glyph::FontArc,
-    image_type: ImageType,
-    project_name: &str,
-    lot_number: &str,
-    prod_date: &str,
-    expire_date: &str,
-    test_counts: &str,
-    layout: &LabelLayout,
-) {
-    let black = image::Luma([0]);
-
-    let title = match image_type {
-        ImageType::ReagentInformation => "试剂二维码信息",
-        ImageType::ExcitationFluidA => "激发液A二维码",
-        ImageType::ExcitationFluidB => "激发液B二维码",
-        ImageType::QualityControl => "质控品二维码",
-        ImageType::CalibrationProduct => "校准品二维码",
-    };
-    draw_centered_bold(canvas, font, 32.0, title, 38, black);
-
-    if matches!(image_type, ImageType::ReagentInformation) {
-        draw_centered(
-            canvas,
-            font,
-            22.0,
-            &format!("{project_name} 测定试剂盒"),
-            75,
-            black,
-        );
-        draw_centered(
-            canvas,
-            font,
-            18.0,
-            &format!("(化学发光免疫分析法)  {test_counts} 测试/盒"),
-            100,
-            black,
-        );
-    } else if matches!(
-        image_type,
-        ImageType::QualityControl | ImageType::CalibrationProduct
-    ) {
-        draw_centered(canvas, font, 24.0, project_name, 72, black);
-    }
-
-    let y0 = layout.footer_y;
-    draw_centered(
-        canvas,
-        font,
-        20.0,
-        &format!("产品批号: {lot_number}"),
-        y0,
-        black,
-    );
-    draw_centered(
-        canvas,
-        font,
-        20.0,
-        &format!("生产日期: {prod_date}"),
-        y0 + 40,
-        black,
-    );
-    draw_centered(
-        canvas,
-        font,
-        20.0,
-        &format!("失效日期: {expire_date}"),
-        y0 + 80,
-        black,
-    );
+    Ok(canvas)
 }
 
-fn draw_centered(
+fn draw_text(
     img: &mut GrayImage,
     font: &ab_glyph::FontArc,
     px: f32,
     text: &str,
+    x: i32,
     y: i32,
-    color: image::Luma<u8>,
+    align: Align,
 ) {
+    let color = image::Luma([0u8]);
+    let (img_w, img_h) = img.dimensions();
     let scaled = font.as_scaled(px);
     let mut total_w = 0.0f32;
     let mut last_id = None;
@@ -225,7 +121,11 @@ fn draw_centered(
         total_w += scaled.h_advance(gid);
         last_id = Some(gid);
     }
-    let mut cursor = (LABEL_W as f32 - total_w) / 2.0;
+    let mut cursor = match align {
+        Align::Left => x as f32,
+        Align::Center => (img_w as f32 - total_w) / 2.0,
+        Align::Right => img_w as f32 - x as f32 - total_w,
+    };
     last_id = None;
     for ch in text.chars() {
         let gid = font.glyph_id(ch);
@@ -242,12 +142,7 @@ fn draw_centered(
             outlined.draw(|gx, gy, alpha: f32| {
                 let px = gx as i32 + bounds.min.x as i32;
                 let py = gy as i32 + bounds.min.y as i32;
-                if px >= 0
-                    && (px as u32) < LABEL_W
-                    && py >= 0
-                    && (py as u32) < LABEL_H
-                    && alpha > 0.1
-                {
+                if px >= 0 && (px as u32) < img_w && py >= 0 && (py as u32) < img_h && alpha > 0.1 {
                     let old = img.get_pixel(px as u32, py as u32)[0] as f32;
                     img.put_pixel(
                         px as u32,
@@ -262,121 +157,545 @@ fn draw_centered(
     }
 }
 
-fn draw_centered_bold(
+fn draw_text_bold(
     img: &mut GrayImage,
     font: &ab_glyph::FontArc,
     px: f32,
     text: &str,
+    x: i32,
     y: i32,
-    color: image::Luma<u8>,
+    align: Align,
 ) {
-    draw_centered(img, font, px, text, y, color);
-    draw_centered(img, font, px, text, y, color);
-    draw_centered(img, font, px, text, y + 1, color);
-    draw_centered(img, font, px, text, y, color);
+    draw_text(img, font, px, text, x, y, align);
+    draw_text(img, font, px, text, x, y, align);
+    draw_text(img, font, px, text, x, y + 1, align);
+    draw_text(img, font, px, text, x, y, align);
+}
+
+/// Save grayscale PNG with `dpi` in its pHYs chunk (300 DPI is 11811 px/m).
+pub fn save_png(
+    gray: &GrayImage,
+    path: impl AsRef<std::path::Path>,
+    dpi: u32,
+) -> Result<(), String> {
+    let pixels_per_meter = (dpi as f32 / 0.0254).round() as u32;
+
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    let buf = std::io::BufWriter::new(file);
+
+    let mut encoder = png::Encoder::new(buf, gray.width(), gray.height());
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
+        unit: png::Unit::Meter,
+    }));
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_image_data(gray.as_raw())
+        .map_err(|e| e.to_string())
+}
+
+/// One label for [`generate_pdf`] and [`generate_zpl`].
+pub struct VectorLabel<'a> {
+    pub template: &'a LabelTemplate,
+    /// Encoded barcode content.
+    pub content: &'a str,
+    /// PDF417 symbol from [`generate_modules`].
+    pub modules: &'a GrayImage,
+    /// Rendered text of each `template.texts` entry.
+    pub texts: &'a [String],
+    /// Raster label from [`draw_barcode_with_text`].
+    pub image: &'a GrayImage,
+}
+
+/// Merge the dark modules into rectangles `(x, y, w, h)`: horizontal runs
+/// first, then identical runs on consecutive rows (PDF417 rows repeat).
+fn module_rects(modules: &GrayImage) -> Vec<(u32, u32, u32, u32)> {
+    let mut done = Vec::new();
+    let mut open: Vec<(u32, u32, u32, u32)> = Vec::new();
+    for y in 0..modules.height() {
+        let mut runs = Vec::new();
+        let mut x = 0;
+        while x < modules.width() {
+            if modules.get_pixel(x, y)[0] < 128 {
+                let start = x;
+                while x < modules.width() && modules.get_pixel(x, y)[0] < 128 {
+                    x += 1;
+                }
+                runs.push((start, x - start));
+            } else {
+                x += 1;
+            }
+        }
+        let mut next = Vec::with_capacity(runs.len());
+        for (rx, rw) in runs {
+            match open.iter().position(|r| r.0 == rx && r.2 == rw) {
+                Some(i) => {
+                    let mut r = open.swap_remove(i);
+                    r.3 += 1;
+                    next.push(r);
+                }
+                None => next.push((rx, y, rw, 1)),
+            }
+        }
+        done.append(&mut open);
+        open = next;
+    }
+    done.append(&mut open);
+    done
 }
 
-/// Generate A4 PDF with barcode images
-pub fn generate_pdf(images: &[GrayImage], output_path: &str) -> Result<(), String> {
+/// An embedded font and the glyphs used from it.
+struct PdfFont {
+    font: &'static font::LabelFont,
+    glyphs: std::collections::BTreeMap<u16, char>,
+}
+
+/// Labels that start on a fresh page of the PDF; a titled section also gets
+/// a bookmark.
+pub struct PdfSection<'a> {
+    pub title: String,
+    pub labels: Vec<VectorLabel<'a>>,
+}
+
+/// Generate a PDF on `sheet` stock with barcodes drawn as vector modules and
+/// label text set in an embedded font subset.
+///
+/// The first `skip` cells of the first page are left empty so a partly used
+/// sheet can be fed again; `offset` (mm, right/down) is the printer calibration.
+pub fn generate_pdf(
+    sections: &[PdfSection],
+    sheet: &SheetTemplate,
+    skip: usize,
+    offset: (f32, f32),
+    output_path: &str,
+) -> Result<(), String> {
     use miniz_oxide::deflate::compress_to_vec_zlib;
-    use pdf_writer::{Content, Filter, Name, Pdf, Rect, Ref};
+    use pdf_writer::types::{
+        CidFontType, FontFlags, PageMode, SystemInfo, TextRenderingMode, UnicodeCmap,
+    };
+    use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
 
-    if images.is_empty() {
+    if sections.iter().all(|s| s.labels.is_empty()) {
         return Err("没有可生成的图像".into());
     }
+    let per_page = sheet.per_page();
+    if per_page == 0 {
+        return Err("标签纸行列数不能为0".into());
+    }
+    if skip >= per_page {
+        return Err(format!("跳过格数应小于每页格数 {per_page}"));
+    }
+
+    // (first cell, labels) of every page, and the first page of each titled section.
+    let mut pages: Vec<(usize, &[VectorLabel])> = Vec::new();
+    let mut bookmarks: Vec<(&str, usize)> = Vec::new();
+    let mut first_cell = skip;
+    for section in sections.iter().filter(|s| !s.labels.is_empty()) {
+        if !section.title.is_empty() {
+            bookmarks.push((&section.title, pages.len()));
+        }
+        let mut rest = &section.labels[..];
+        while !rest.is_empty() {
+            let n = (per_page - first_cell).min(rest.len());
+            pages.push((first_cell, &rest[..n]));
+            rest = &rest[n..];
+            first_cell = 0;
+        }
+    }
 
     let mut pdf = Pdf::new();
     pdf.set_version(1, 7);
 
     let catalog_id = Ref::new(1);
     let pages_id = Ref::new(2);
-    pdf.catalog(catalog_id).pages(pages_id);
 
-    let per_page = 12usize;
-    let cols = 3usize;
-    let margin = 28.35f32;
-    let cell_w = 155.91f32;
-    let cell_h = 127.56f32;
-    let page_w = 595.28f32;
-    let page_h = 841.89f32;
+    let cell_w = sheet.cell_width * PT_PER_MM;
+    let cell_h = sheet.cell_height * PT_PER_MM;
+    let page_w = sheet.page_width * PT_PER_MM;
+    let page_h = sheet.page_height * PT_PER_MM;
 
-    let page_count = (images.len() + per_page - 1) / per_page;
+    let page_count = pages.len();
     let mut next_id: i32 = 3;
+    let mut alloc = || {
+        let r = Ref::new(next_id);
+        next_id += 1;
+        r
+    };
 
-    let page_ids: Vec<Ref> = (0..page_count)
-        .map(|_| {
-            let r = Ref::new(next_id);
-            next_id += 1;
-            r
-        })
-        .collect();
-    let cont_ids: Vec<Ref> = (0..page_count)
-        .map(|_| {
-            let r = Ref::new(next_id);
-            next_id += 1;
-            r
-        })
-        .collect();
-    let xobj_ids: Vec<Ref> = (0..images.len())
-        .map(|_| {
-            let r = Ref::new(next_id);
-            next_id += 1;
-            r
-        })
-        .collect();
+    let page_ids: Vec<Ref> = (0..page_count).map(|_| alloc()).collect();
+    let cont_ids: Vec<Ref> = (0..page_count).map(|_| alloc()).collect();
 
     pdf.pages(pages_id)
         .kids(page_ids.iter().copied())
         .count(page_count as i32);
 
-    for pi in 0..page_count {
-        let start = pi * per_page;
-        let end = (start + per_page).min(images.len());
-        let page_imgs = &images[start..end];
-
-        // Write compressed image XObjects
-        for (i, img) in page_imgs.iter().enumerate() {
-            let xobj_id = xobj_ids[start + i];
-            let compressed = compress_to_vec_zlib(img.as_raw(), 6);
-            let mut xobj = pdf.image_xobject(xobj_id, &compressed);
-            xobj.filter(Filter::FlateDecode);
-            xobj.width(img.width() as i32);
-            xobj.height(img.height() as i32);
-            xobj.color_space().device_gray();
-            xobj.bits_per_component(8);
+    {
+        let mut catalog = pdf.catalog(catalog_id);
+        catalog.pages(pages_id);
+        if !bookmarks.is_empty() {
+            let outline_id = alloc();
+            let item_ids: Vec<Ref> = bookmarks.iter().map(|_| alloc()).collect();
+            catalog
+                .outlines(outline_id)
+                .page_mode(PageMode::UseOutlines);
+            catalog.finish();
+            pdf.outline(outline_id)
+                .first(item_ids[0])
+                .last(item_ids[item_ids.len() - 1])
+                .count(item_ids.len() as i32);
+            for (i, (title, page)) in bookmarks.iter().enumerate() {
+                let mut item = pdf.outline_item(item_ids[i]);
+                item.title(TextStr(title)).parent(outline_id);
+                if i > 0 {
+                    item.prev(item_ids[i - 1]);
+                }
+                if let Some(next) = item_ids.get(i + 1) {
+                    item.next(*next);
+                }
+                item.dest().page(page_ids[*page]).xyz(0.0, page_h, None);
+            }
         }
+    }
+
+    let mut fonts: Vec<PdfFont> = Vec::new();
 
-        // Build content stream
+    for ((first_cell, labels), cont_id) in pages.iter().zip(&cont_ids) {
         let mut content = Content::new();
-        for (i, _) in page_imgs.iter().enumerate() {
-            let pos = i % per_page;
-            let row = pos / cols;
-            let col = pos % cols;
-            let x = margin + col as f32 * cell_w;
-            let y = page_h - margin - (row as f32 + 1.0) * cell_h;
+        for (i, label) in labels.iter().enumerate() {
+            let t = label.template;
+            let (cx, cy) = sheet.cell_origin(first_cell + i);
+            // Fit the label into the cell keeping its aspect ratio, centered.
+            let scale = (cell_w / t.width as f32).min(cell_h / t.height as f32);
+            let x = (cx + offset.0) * PT_PER_MM + (cell_w - t.width as f32 * scale) / 2.0;
+            let top =
+                page_h - (cy + offset.1) * PT_PER_MM - (cell_h - t.height as f32 * scale) / 2.0;
+
+            // Label pixels → points, y pointing down like the raster layout.
             content.save_state();
-            content.transform([cell_w, 0.0, 0.0, cell_h, x, y]);
-            content.x_object(Name(format!("Im{}", start + i).as_bytes()));
+            content.transform([scale, 0.0, 0.0, -scale, x, top]);
+            content.set_fill_gray(0.0);
+
+            let b = &t.barcode;
+            let m = label.modules;
+            content.save_state();
+            content.transform([
+                b.width as f32 / m.width() as f32,
+                0.0,
+                0.0,
+                b.height as f32 / m.height() as f32,
+                b.x as f32,
+                b.y as f32,
+            ]);
+            for (rx, ry, rw, rh) in module_rects(m) {
+                content.rect(rx as f32, ry as f32, rw as f32, rh as f32);
+            }
+            content.fill_nonzero();
+            content.restore_state();
+
+            for (el, text) in t.texts.iter().zip(label.texts) {
+                if text.trim().is_empty() {
+                    continue;
+                }
+                let lf = font::font_for(text)?;
+                let fi = match fonts.iter().position(|f| std::ptr::eq(f.font, lf)) {
+                    Some(i) => i,
+                    None => {
+                        fonts.push(PdfFont {
+                            font: lf,
+                            glyphs: Default::default(),
+                        });
+                        fonts.len() - 1
+                    }
+                };
+                let f = &lf.font;
+                // ab_glyph sizes text by ascent-to-descent height, PDF by em.
+                let upem = f.units_per_em().unwrap_or(1000.0);
+                let size = el.size * upem / f.height_unscaled();
+                let mut encoded = Vec::new();
+                let mut advance = 0.0f32;
+                for ch in text.chars() {
+                    let gid = f.glyph_id(ch);
+                    fonts[fi].glyphs.insert(gid.0, ch);
+                    encoded.extend_from_slice(&gid.0.to_be_bytes());
+                    advance += f.h_advance_unscaled(gid);
+                }
+                let width = advance * size / upem;
+                let tx = match el.align {
+                    Align::Left => el.x as f32,
+                    Align::Center => (t.width as f32 - width) / 2.0,
+                    Align::Right => t.width as f32 - el.x as f32 - width,
+                };
+
+                content.begin_text();
+                content.set_font(Name(format!("F{fi}").as_bytes()), size);
+                if el.bold {
+                    content.set_text_rendering_mode(TextRenderingMode::FillStroke);
+                    content.set_line_width(size * 0.04);
+                }
+                content.set_text_matrix([1.0, 0.0, 0.0, -1.0, tx, el.y as f32]);
+                content.show(Str(&encoded));
+                content.end_text();
+                if el.bold {
+                    content.set_text_rendering_mode(TextRenderingMode::Fill);
+                }
+            }
             content.restore_state();
         }
-        pdf.stream(cont_ids[pi], &content.finish());
+        pdf.stream(*cont_id, &content.finish());
+    }
+
+    // Fonts are shared by every page.
+    let font_ids: Vec<Ref> = fonts.iter().map(|_| alloc()).collect();
+    for (pi, page_id) in page_ids.iter().enumerate() {
+        let mut page = pdf.page(*page_id);
+        page.media_box(Rect::new(0.0, 0.0, page_w, page_h));
+        page.parent(pages_id);
+        page.contents(cont_ids[pi]);
+        let mut res = page.resources();
+        let mut res_fonts = res.fonts();
+        for (fi, id) in font_ids.iter().enumerate() {
+            res_fonts.pair(Name(format!("F{fi}").as_bytes()), *id);
+        }
+    }
+
+    let system_info = SystemInfo {
+        registry: Str(b"Adobe"),
+        ordering: Str(b"Identity"),
+        supplement: 0,
+    };
+    for (fi, pf) in fonts.iter().enumerate() {
+        let f = &pf.font.font;
+        let upem = f.units_per_em().unwrap_or(1000.0);
+        let to_pdf = |v: f32| v * 1000.0 / upem;
+
+        let mut gids: Vec<u16> = pf.glyphs.keys().copied().collect();
+        gids.insert(0, 0);
+        let subset = subsetter::subset(pf.font.data, 0, subsetter::Profile::pdf(&gids))
+            .map_err(|e| format!("字体子集化失败: {e:?}"))?;
+        let is_cff = subset.starts_with(b"OTTO");
+        let base_font = format!("RLCLI{}+LabelFont{fi}", (b'A' + fi as u8 % 26) as char);
+        let base_font = Name(base_font.as_bytes());
 
-        // Write page
+        let cid_id = alloc();
+        let descriptor_id = alloc();
+        let file_id = alloc();
+        let cmap_id = alloc();
+
+        pdf.type0_font(font_ids[fi])
+            .base_font(base_font)
+            .encoding_predefined(Name(b"Identity-H"))
+            .descendant_font(cid_id)
+            .to_unicode(cmap_id);
+
+        let mut cid = pdf.cid_font(cid_id);
+        cid.subtype(if is_cff {
+            CidFontType::Type0
+        } else {
+            CidFontType::Type2
+        });
+        cid.base_font(base_font);
+        cid.system_info(system_info);
+        cid.font_descriptor(descriptor_id);
+        cid.default_width(0.0);
+        if !is_cff {
+            cid.cid_to_gid_map_predefined(Name(b"Identity"));
+        }
         {
-            let mut page = pdf.page(page_ids[pi]);
-            page.media_box(Rect::new(0.0, 0.0, page_w, page_h));
-            page.parent(pages_id);
-            page.contents(cont_ids[pi]);
-            let mut res = page.resources();
-            for (i, _) in page_imgs.iter().enumerate() {
-                res.pair(
-                    Name(format!("Im{}", start + i).as_bytes()),
-                    xobj_ids[start + i],
-                );
+            let mut widths = cid.widths();
+            for &gid in pf.glyphs.keys() {
+                let w = to_pdf(f.h_advance_unscaled(ab_glyph::GlyphId(gid)));
+                widths.consecutive(gid, [w]);
             }
         }
+        cid.finish();
+
+        let ascent = to_pdf(f.ascent_unscaled());
+        let descent = to_pdf(f.descent_unscaled());
+        let mut descriptor = pdf.font_descriptor(descriptor_id);
+        descriptor
+            .name(base_font)
+            .flags(FontFlags::SYMBOLIC)
+            .bbox(Rect::new(0.0, descent, 1000.0, ascent))
+            .italic_angle(0.0)
+            .ascent(ascent)
+            .descent(descent)
+            .cap_height(ascent)
+            .stem_v(80.0);
+        if is_cff {
+            descriptor.font_file3(file_id);
+        } else {
+            descriptor.font_file2(file_id);
+        }
+        descriptor.finish();
+
+        let compressed = compress_to_vec_zlib(&subset, 6);
+        let mut file = pdf.stream(file_id, &compressed);
+        file.filter(Filter::FlateDecode);
+        if is_cff {
+            file.pair(Name(b"Subtype"), Name(b"OpenType"));
+        }
+        file.finish();
+
+        let mut cmap = UnicodeCmap::new(Name(b"Custom"), system_info);
+        for (&gid, &ch) in &pf.glyphs {
+            cmap.pair(gid, ch);
+        }
+        pdf.stream(cmap_id, &cmap.finish());
     }
 
     let bytes = pdf.finish();
     std::fs::write(output_path, &bytes).map_err(|e| format!("保存PDF失败: {e}"))
 }
+
+/// Convert labels to ZPL, one template pixel per printer dot; scale the
+/// templates with [`LabelTemplate::scaled`] for printers other than 300 DPI.
+///
+/// By default the barcode is a native `^B7` and text uses the printer font
+/// `font`; with `graphic` each label is sent as one `^GF` image instead, for
+/// printers without a CJK font.
+pub fn generate_zpl(labels: &[VectorLabel], graphic: bool, font: &str) -> String {
+    use rust_learning::zpl::{self, Element, Orientation, TextAlign};
+
+    const COLUMNS: u32 = 3;
+    let zpl_labels: Vec<zpl::Label> = labels
+        .iter()
+        .map(|label| {
+            let t = label.template;
+            let mut elements = Vec::new();
+            if graphic {
+                elements.push(Element::Graphic {
+                    x: 0,
+                    y: 0,
+                    image: label.image.clone(),
+                });
+            } else {
+                let b = &t.barcode;
+                let width = zpl::pdf417_width_modules(COLUMNS, false);
+                let module = (b.width / width).max(1);
+                let rows = zpl::pdf417_rows(label.modules).max(3);
+                elements.push(Element::Pdf417 {
+                    x: b.x + b.width.saturating_sub(module * width) / 2,
+                    y: b.y,
+                    module,
+                    row_height: (b.height / rows).max(1),
+                    columns: COLUMNS,
+                    rows,
+                    security: 0,
+                    compact: false,
+                    orientation: Orientation::Normal,
+                    data: label.content.to_string(),
+                });
+                for (el, text) in t.texts.iter().zip(label.texts) {
+                    if text.trim().is_empty() {
+                        continue;
+                    }
+                    // Template y is the baseline, ^FO the top of the text.
+                    let top = (el.y as f32 - el.size * 0.8).max(0.0) as u32;
+                    let x = el.x.max(0) as u32;
+                    let (x, width, align) = match el.align {
+                        Align::Left => (x, t.width.saturating_sub(x), TextAlign::Left),
+                        Align::Center => (0, t.width, TextAlign::Center),
+                        Align::Right => (0, t.width.saturating_sub(x), TextAlign::Right),
+                    };
+                    elements.push(Element::Text {
+                        x,
+                        y: top,
+                        width,
+                        height: el.size.round() as u32,
+                        align,
+                        data: text.clone(),
+                    });
+                }
+            }
+            zpl::Label {
+                width: t.width,
+                height: t.height,
+                elements,
+            }
+        })
+        .collect();
+    zpl::to_zpl(&zpl_labels, font)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::assert_golden;
+    use crate::template::{BarcodeBox, LabelTemplates, TEMPLATE_DPI};
+
+    #[test]
+    fn zpl_export_matches_golden() {
+        let templates = LabelTemplates::default();
+        let fields = [
+            ("project", "cTnI"),
+            ("lot", "L_01^"),
+            ("prod_date", "2026-01-01"),
+            ("expire_date", "2027-01-01"),
+            ("test_counts", "100"),
+        ];
+        // 20 PDF417 rows of 3 pixels, each with a different module pattern.
+        let modules = GrayImage::from_fn(120, 60, |x, y| {
+            image::Luma([if (x / 2 + y / 3) % 3 == 0 { 0 } else { 255 }])
+        });
+        let image = GrayImage::new(1, 1);
+        let laid_out: Vec<(LabelTemplate, Vec<String>)> = [
+            (ImageType::ReagentInformation, TEMPLATE_DPI),
+            (ImageType::QualityControl, TEMPLATE_DPI),
+            (ImageType::CalibrationProduct, 203),
+        ]
+        .into_iter()
+        .map(|(typ, dpi)| {
+            let t = templates.get(typ).scaled(dpi);
+            let texts = t.texts.iter().map(|e| e.render(&fields)).collect();
+            (t, texts)
+        })
+        .collect();
+        let labels: Vec<VectorLabel> = laid_out
+            .iter()
+            .map(|(template, texts)| VectorLabel {
+                template,
+                content: "RL2.k20260101.AbC+/9=",
+                modules: &modules,
+                texts,
+                image: &image,
+            })
+            .collect();
+        assert_golden("rl_clia.zpl", &generate_zpl(&labels, false, "E:SIMSUN.TTF"));
+    }
+
+    #[test]
+    fn barcode_is_enlarged_by_whole_factors_and_centred() {
+        let template = LabelTemplate {
+            width: 200,
+            height: 100,
+            barcode: BarcodeBox {
+                x: 10,
+                y: 5,
+                width: 100,
+                height: 60,
+            },
+            texts: vec![],
+        };
+        // 100 / 23 → 4 across, 60 / 7 → 8 down.
+        let modules = GrayImage::from_fn(23, 7, |x, y| {
+            image::Luma([if (x + y) % 2 == 0 { 0 } else { 255 }])
+        });
+        let label = draw_barcode_with_text(&modules, &template, &[]).unwrap();
+        let (left, top) = (10 + (100 - 23 * 4) / 2, 5 + (60 - 7 * 8) / 2);
+        for y in 0..template.height {
+            for x in 0..template.width {
+                let inside = (left..left + 23 * 4).contains(&x) && (top..top + 7 * 8).contains(&y);
+                let expected = if inside {
+                    modules.get_pixel((x - left) / 4, (y - top) / 8)[0]
+                } else {
+                    255
+                };
+                assert_eq!(label.get_pixel(x, y)[0], expected, "({x}, {y})");
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Headless batch input: a planning spreadsheet with one row per lot.
+//!
+//! `RL-CLIA batch <计划.xlsx|计划.csv> [--out <目录>] [--sheet <标签纸>]
+//! [--name <文件名模板>] [--passphrase <密码>] [--site-passphrase <密码>]`
+//!
+//! The first non-blank row holds the column headers below; only the columns
+//! a type needs have to be present. Blank optional cells take the same
+//! defaults as the window. CSV files may be UTF-8 or GBK, as Excel saves them,
+//! and their cells must not contain line breaks.
+
+use crate::validation::{decode_text, split_csv_line};
+use calamine::{open_workbook_auto, Data, Reader};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const COL_TYPE: &str = "类型";
+/// Project name; for consumables `激发液A` or `激发液B`.
+pub const COL_PROJECT: &str = "项目";
+pub const COL_LOT: &str = "批号";
+pub const COL_PROD_DATE: &str = "生产日期";
+pub const COL_VALID_DAYS: &str = "有效天数";
+pub const COL_QUANTITY: &str = "数量";
+pub const COL_TEST_COUNTS: &str = "测试数";
+pub const COL_OPEN_DAYS: &str = "开瓶天数";
+pub const COL_UNIT: &str = "单位";
+pub const COL_CURVE: [&str; 4] = ["曲线a", "曲线b", "曲线c", "曲线d"];
+pub const COL_RANGE_LOW: &str = "范围下限";
+pub const COL_RANGE_UPPER: &str = "范围上限";
+pub const COL_LIMIT_LOW: &str = "限值下限";
+pub const COL_LIMIT_UPPER: &str = "限值上限";
+pub const COL_C1: &str = "C1";
+pub const COL_C2: &str = "C2";
+pub const COL_FREQ: &str = "可用频次";
+pub const COL_Q1: &str = "Q1";
+pub const COL_SD1: &str = "SD1";
+pub const COL_Q2: &str = "Q2";
+pub const COL_SD2: &str = "SD2";
+
+/// Result report written next to the generated files.
+pub const REPORT_NAME: &str = "report.csv";
+
+pub const USAGE: &str =
+    "用法: RL-CLIA batch <计划.xlsx|计划.csv> [--out <目录>] [--sheet <标签纸>] \
+[--name <文件名模板>] [--passphrase <密码>] [--site-passphrase <密码>]";
+
+/// Command-line options of batch mode.
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub input: PathBuf,
+    /// Defaults to `<输入文件名>_输出` beside the input.
+    pub out: PathBuf,
+    /// Label sheet for the PDFs; the first configured sheet when absent.
+    pub sheet: Option<String>,
+    /// PNG file name template, as in the window.
+    pub name_template: String,
+    /// Unlocks `Setting/keystore.json` when it exists (otherwise the legacy
+    /// key is used). Also read from `RLCLIA_PASSPHRASE`.
+    pub passphrase: Option<String>,
+    /// Unlocks the site key that signs the manifest. Also read from
+    /// `RLCLIA_SITE_PASSPHRASE`; falls back to `passphrase`, as the window
+    /// unlocks both with one passphrase. One of the two is required.
+    pub site_passphrase: Option<String>,
+}
+
+/// Parse the arguments following `batch`.
+pub fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut input = None;
+    let mut out = None;
+    let mut sheet = None;
+    let mut name_template = "{type}_{project}_{lot}_{serial}".to_string();
+    let mut passphrase = std::env::var("RLCLIA_PASSPHRASE").ok();
+    let mut site_passphrase = std::env::var("RLCLIA_SITE_PASSPHRASE").ok();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| format!("{arg} 缺少参数值"))
+        };
+        match arg.as_str() {
+            "--out" => out = Some(PathBuf::from(value()?)),
+            "--sheet" => sheet = Some(value()?),
+            "--name" => name_template = value()?,
+            "--passphrase" => passphrase = Some(value()?),
+            "--site-passphrase" => site_passphrase = Some(value()?),
+            s if s.starts_with("--") => return Err(format!("未知选项: {s}")),
+            s if input.is_none() => input = Some(PathBuf::from(s)),
+            s => return Err(format!("多余的参数: {s}")),
+        }
+    }
+    let input = input.ok_or("缺少输入文件")?;
+    let out = out.unwrap_or_else(|| {
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        input.with_file_name(format!("{stem}_输出"))
+    });
+    Ok(Args {
+        input,
+        out,
+        sheet,
+        name_template,
+        passphrase,
+        site_passphrase,
+    })
+}
+
+/// One data row, keyed by column header.
+#[derive(Debug, Clone)]
+pub struct Row {
+    /// 1-based row number in the spreadsheet, for the report.
+    pub line: usize,
+    cells: HashMap<String, String>,
+}
+
+impl Row {
+    /// Trimmed cell text, empty when the column is missing.
+    pub fn get(&self, col: &str) -> String {
+        self.cells
+            .get(col)
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Like [`Row::get`], with `default` for a blank cell.
+    pub fn get_or(&self, col: &str, default: &str) -> String {
+        let v = self.get(col);
+        if v.is_empty() {
+            default.to_string()
+        } else {
+            v
+        }
+    }
+}
+
+/// Map the type column to the window's page key.
+pub fn type_key(value: &str) -> Result<&'static str, String> {
+    match value.trim().to_lowercase().as_str() {
+        "reagent" | "试剂" => Ok("reagent"),
+        "calibration" | "校准品" => Ok("calibration"),
+        "consumable" | "耗材" => Ok("consumable"),
+        "qc" | "quality" | "质控品" => Ok("quality"),
+        "" => Err(format!("缺少“{COL_TYPE}”")),
+        other => Err(format!(
+            "未知类型“{other}”，应为 reagent / calibration / consumable / qc"
+        )),
+    }
+}
+
+/// Read every non-blank data row of an `.xlsx` / `.xls` / `.ods` workbook's
+/// first sheet, or of a `.csv` file.
+pub fn read_rows(path: &Path) -> Result<Vec<Row>, String> {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let table = if ext == "csv" {
+        let bytes =
+            std::fs::read(path).map_err(|e| format!("读取 {} 失败: {e}", path.display()))?;
+        csv_table(&decode_text(&bytes))?
+    } else {
+        let mut workbook = open_workbook_auto(path).map_err(|e| format!("打开表格失败: {e}"))?;
+        let range = workbook
+            .worksheet_range_at(0)
+            .ok_or("表格没有工作表")?
+            .map_err(|e| format!("读取工作表失败: {e}"))?;
+        // Keep spreadsheet row numbers when the used range starts lower down.
+        let skipped = range.start().map_or(0, |(row, _)| row as usize);
+        std::iter::repeat_n(Vec::new(), skipped)
+            .chain(range.rows().map(|r| r.iter().map(cell_text).collect()))
+            .collect::<Vec<Vec<String>>>()
+    };
+    rows_from_table(table)
+}
+
+/// One row per line. Excel quotes a cell that contains a line break and
+/// continues it on the next line; such a file is rejected rather than read
+/// as two broken rows.
+fn csv_table(text: &str) -> Result<Vec<Vec<String>>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if line.matches('"').count() % 2 == 1 {
+                return Err(format!(
+                    "CSV 第 {} 行引号不成对：单元格内不能换行，请删除换行后重新保存",
+                    i + 1
+                ));
+            }
+            Ok(split_csv_line(line))
+        })
+        .collect()
+}
+
+fn rows_from_table(table: Vec<Vec<String>>) -> Result<Vec<Row>, String> {
+    let blank = |r: &[String]| r.iter().all(|c| c.trim().is_empty());
+    let mut lines = table.into_iter().enumerate().filter(|(_, r)| !blank(r));
+    let (_, headers) = lines.next().ok_or("表格为空")?;
+    let headers: Vec<String> = headers.iter().map(|h| h.trim().to_string()).collect();
+    if !headers.iter().any(|h| h == COL_TYPE) {
+        return Err(format!("表头缺少“{COL_TYPE}”列"));
+    }
+    Ok(lines
+        .map(|(i, cells)| {
+            let cells = headers
+                .iter()
+                .zip(cells)
+                .map(|(h, c)| {
+                    let c = if h == COL_PROD_DATE {
+                        normalize_date(&c)
+                    } else {
+                        c
+                    };
+                    (h.clone(), c)
+                })
+                .collect();
+            Row { line: i + 1, cells }
+        })
+        .collect())
+}
+
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::DateTime(dt) => excel_date(dt.as_f64()),
+        other => other.to_string(),
+    }
+}
+
+/// Excel serial day number (1900 date system) to `YYYY-MM-DD`.
+fn excel_date(serial: f64) -> String {
+    NaiveDate::from_ymd_opt(1899, 12, 30)
+        .and_then(|d| d.checked_add_signed(chrono::Duration::days(serial.floor() as i64)))
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Excel writes dates into CSV as `2025/3/1`; validation wants `2025-03-01`.
+fn normalize_date(s: &str) -> String {
+    let s = s.trim();
+    NaiveDate::parse_from_str(s, "%Y/%m/%d")
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|_| s.to_string())
+}
+
+/// Per-row outcome, saved as a UTF-8 CSV that Excel opens directly.
+#[derive(Debug, Default)]
+pub struct Report {
+    lines: Vec<[String; 7]>,
+}
+
+impl Report {
+    pub fn push(&mut self, row: &Row, ok: bool, count: usize, detail: &str) {
+        self.lines.push([
+            row.line.to_string(),
+            row.get(COL_TYPE),
+            row.get(COL_PROJECT),
+            row.get(COL_LOT),
+            if ok { "成功" } else { "失败" }.to_string(),
+            count.to_string(),
+            detail.to_string(),
+        ]);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let header = ["行号", "类型", "项目", "批号", "结果", "张数", "说明"];
+        let mut text = String::from('\u{feff}');
+        text.push_str(&header.join(","));
+        text.push_str("\r\n");
+        for line in &self.lines {
+            let cols: Vec<String> = line.iter().map(|c| csv_field(c)).collect();
+            text.push_str(&cols.join(","));
+            text.push_str("\r\n");
+        }
+        std::fs::write(path, text).map_err(|e| format!("写入报告失败: {e}"))
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Result<Args, String> {
+        let list: Vec<String> = list.iter().map(|s| s.to_string()).collect();
+        parse_args(&list)
+    }
+
+    fn table(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|r| r.iter().map(|c| c.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn parse_args_reads_options_and_defaults_the_output() {
+        let a = args(&["plan/计划.csv", "--sheet", "A4", "--site-passphrase", "s"]).unwrap();
+        assert_eq!(a.input, PathBuf::from("plan/计划.csv"));
+        assert_eq!(a.out, PathBuf::from("plan/计划_输出"));
+        assert_eq!(a.sheet.as_deref(), Some("A4"));
+        assert_eq!(a.name_template, "{type}_{project}_{lot}_{serial}");
+        assert_eq!(a.site_passphrase.as_deref(), Some("s"));
+
+        let a = args(&["--out", "out", "计划.xlsx"]).unwrap();
+        assert_eq!(a.out, PathBuf::from("out"));
+    }
+
+    #[test]
+    fn parse_args_rejects_bad_input() {
+        assert_eq!(
+            args(&["计划.csv", "--out"]).unwrap_err(),
+            "--out 缺少参数值"
+        );
+        assert_eq!(
+            args(&["计划.csv", "--pass", "x"]).unwrap_err(),
+            "未知选项: --pass"
+        );
+        assert_eq!(args(&["a.csv", "b.csv"]).unwrap_err(), "多余的参数: b.csv");
+        assert_eq!(args(&[]).unwrap_err(), "缺少输入文件");
+    }
+
+    #[test]
+    fn rows_keep_spreadsheet_numbers_past_blank_rows() {
+        let rows = rows_from_table(table(&[
+            &["", ""],
+            &[" 类型 ", "生产日期"],
+            &["reagent", "2025/3/1"],
+            &[" ", ""],
+            &["qc"],
+        ]))
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].line, rows[1].line), (3, 5));
+        assert_eq!(rows[0].get(COL_PROD_DATE), "2025-03-01");
+        assert_eq!(rows[1].get(COL_TYPE), "qc");
+        assert_eq!(rows[1].get_or(COL_PROD_DATE, "-"), "-");
+
+        assert!(rows_from_table(table(&[&["项目"], &["x"]])).is_err());
+        assert_eq!(rows_from_table(table(&[&[""]])).unwrap_err(), "表格为空");
+    }
+
+    #[test]
+    fn dates_are_normalized() {
+        assert_eq!(normalize_date(" 2025/3/1 "), "2025-03-01");
+        assert_eq!(normalize_date("2025-03-01"), "2025-03-01");
+        assert_eq!(normalize_date("三月"), "三月");
+        assert_eq!(excel_date(45658.0), "2025-01-01");
+        assert_eq!(excel_date(45658.75), "2025-01-01");
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn csv_with_a_line_break_inside_a_cell_is_rejected() {
+        let ok = csv_table("类型,项目\nreagent,\"A, \"\"B\"\"\"\n").unwrap();
+        assert_eq!(ok[1], ["reagent", "A, \"B\""]);
+
+        let err = csv_table("类型,项目\nreagent,\"A\nB\"\n").unwrap_err();
+        assert!(err.contains("第 2 行"), "{err}");
+    }
+}
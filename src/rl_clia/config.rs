@@ -1,25 +1,173 @@
-use serde::Deserialize;
-use std::path::Path;
+use rust_learning::mono::MonoOptions;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone, Deserialize)]
+/// Project and consumable catalog.
+pub const PROJECT_PATH: &str = "Setting/project.json";
+
+/// Result units offered when a project does not list its own.
+pub const UNITS: [&str; 5] = ["pg/mL", "ng/mL", "mg/L", "ng/L", "IU/L"];
+
+/// Master data of one assay in `Setting/project.json`. The reagent,
+/// calibration and QC forms prefill from it when the project is picked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    /// Units the reagent result may be reported in; the first is the default.
+    #[serde(default = "default_units")]
+    pub units: Vec<String>,
+    #[serde(default = "default_valid_days")]
+    pub valid_days: u32,
+    /// Tests per reagent kit.
+    #[serde(default = "default_test_counts")]
+    pub test_counts: u32,
+    /// Days a reagent stays usable once opened.
+    #[serde(default = "default_open_days")]
+    pub open_days: u32,
+    #[serde(default = "default_reaction_mode")]
+    pub reaction_mode: String,
+    /// Default calibration curve parameters a–d, as typed into the form.
+    #[serde(default)]
+    pub curve: [String; 4],
+    /// Retired projects stay in the file but are no longer offered.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retired: bool,
+}
+
+fn default_units() -> Vec<String> {
+    UNITS.iter().map(|u| u.to_string()).collect()
+}
+
+fn default_valid_days() -> u32 {
+    365
+}
+
+fn default_test_counts() -> u32 {
+    50
+}
+
+fn default_open_days() -> u32 {
+    28
+}
+
+fn default_reaction_mode() -> String {
+    "direct".to_string()
+}
+
+impl Project {
+    /// A project with the default master data.
+    pub fn new(id: &str, name: &str) -> Project {
+        Project {
+            id: id.to_string(),
+            name: name.to_string(),
+            units: default_units(),
+            valid_days: default_valid_days(),
+            test_counts: default_test_counts(),
+            open_days: default_open_days(),
+            reaction_mode: default_reaction_mode(),
+            curve: Default::default(),
+            retired: false,
+        }
+    }
+}
+
+/// Which excitation fluid label layout a consumable prints with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum FluidLabel {
+    #[default]
+    A,
+    B,
+}
+
+/// A consumable type, e.g. `激发液A`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Consumable {
+    pub name: String,
+    #[serde(default)]
+    pub label: FluidLabel,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retired: bool,
+}
+
+fn default_consumables() -> Vec<Consumable> {
+    [("激发液A", FluidLabel::A), ("激发液B", FluidLabel::B)]
+        .into_iter()
+        .map(|(name, label)| Consumable {
+            name: name.to_string(),
+            label,
+            retired: false,
+        })
+        .collect()
+}
+
+/// ASCII spellings for names the instrument cannot read as-is. Consumables
+/// are not listed: until their ASCII names are confirmed they keep the
+/// encoding labels in the field already carry.
+fn default_transliterations() -> BTreeMap<String, String> {
+    [("S100β", "S100B"), ("Aβ1-42", "AB1-42"), ("β-HCG", "B-HCG")]
+        .into_iter()
+        .map(|(name, ascii)| (name.to_string(), ascii.to_string()))
+        .collect()
+}
+
+/// The project and consumable catalog.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ProjectFile")]
 pub struct ProjectConfig {
-    #[serde(rename = "projectIDList")]
-    pub project_id_list: Vec<String>,
-    #[serde(rename = "projectNameList")]
-    pub project_name_list: Vec<String>,
+    pub projects: Vec<Project>,
+    pub consumables: Vec<Consumable>,
+    /// Catalog name → the ASCII name written into barcodes, e.g.
+    /// `"S100β": "S100B"`. Names not listed are written unchanged.
+    pub transliterations: BTreeMap<String, String>,
 }
 
-pub fn load_project_config() -> ProjectConfig {
-    let path = Path::new("Setting/project.json");
-    if let Ok(data) = std::fs::read_to_string(path) {
-        if let Ok(cfg) = serde_json::from_str(&data) {
-            return cfg;
+/// `Setting/project.json` as stored: a `projects` list, or the original
+/// parallel `projectIDList` / `projectNameList` arrays.
+#[derive(Deserialize)]
+struct ProjectFile {
+    #[serde(default)]
+    projects: Vec<Project>,
+    #[serde(default = "default_consumables")]
+    consumables: Vec<Consumable>,
+    #[serde(default = "default_transliterations")]
+    transliterations: BTreeMap<String, String>,
+    #[serde(rename = "projectIDList", default)]
+    project_id_list: Vec<String>,
+    #[serde(rename = "projectNameList", default)]
+    project_name_list: Vec<String>,
+}
+
+impl From<ProjectFile> for ProjectConfig {
+    fn from(file: ProjectFile) -> Self {
+        let mut projects = file.projects;
+        if projects.is_empty() {
+            projects = file
+                .project_id_list
+                .iter()
+                .zip(&file.project_name_list)
+                .map(|(id, name)| Project::new(id, name))
+                .collect();
+        }
+        for p in &mut projects {
+            if p.units.is_empty() {
+                p.units = default_units();
+            }
+        }
+        ProjectConfig {
+            projects,
+            consumables: file.consumables,
+            transliterations: file.transliterations,
         }
     }
-    // fallback defaults
-    ProjectConfig {
-        project_id_list: (1..=24).map(|i| i.to_string()).collect(),
-        project_name_list: vec![
+}
+
+impl Default for ProjectConfig {
+    /// The built-in catalog used when `Setting/project.json` does not exist.
+    fn default() -> Self {
+        let names = [
             "cTnI",
             "NT-proBNP",
             "Myoglobin",
@@ -44,9 +192,223 @@ pub fn load_project_config() -> ProjectConfig {
             "P-Tau181",
             "AD7c-NTP",
             "β-HCG",
-        ]
-        .iter()
-        .map(|s| s.to_string())
-        .collect(),
+        ];
+        ProjectConfig {
+            projects: names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Project::new(&(i + 1).to_string(), name))
+                .collect(),
+            consumables: default_consumables(),
+            transliterations: default_transliterations(),
+        }
+    }
+}
+
+impl ProjectConfig {
+    /// Projects still in use, in catalog order, as the combo boxes list them.
+    pub fn active(&self) -> Vec<&Project> {
+        self.projects.iter().filter(|p| !p.retired).collect()
     }
+
+    /// Project at combo box `index`.
+    pub fn at(&self, index: i32) -> Option<&Project> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| self.active().get(i).copied())
+    }
+
+    /// Look a project up by name, retired ones included.
+    pub fn find(&self, name: &str) -> Option<&Project> {
+        self.projects.iter().find(|p| p.name == name)
+    }
+
+    pub fn active_consumables(&self) -> Vec<&Consumable> {
+        self.consumables.iter().filter(|c| !c.retired).collect()
+    }
+
+    pub fn find_consumable(&self, name: &str) -> Option<&Consumable> {
+        self.consumables.iter().find(|c| c.name == name)
+    }
+
+    /// The name written into a barcode for catalog name `name`.
+    pub fn instrument_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.transliterations.get(name).map_or(name, String::as_str)
+    }
+
+    /// The catalog name behind a name read from a barcode.
+    pub fn catalog_name<'a>(&'a self, ascii: &'a str) -> &'a str {
+        self.transliterations
+            .iter()
+            .find(|(_, a)| *a == ascii)
+            .map_or(ascii, |(name, _)| name.as_str())
+    }
+
+    /// Check what saving requires: every project has an ID, IDs and names
+    /// are unique (retired entries included, as old labels still carry them)
+    /// and every project offers at least one unit. Every name must also have
+    /// an instrument name that is ASCII, free of `;` and not shared with
+    /// another entry, so that decoding can map it back. A consumable without
+    /// an instrument name keeps its catalog name, ASCII or not.
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, p) in self.projects.iter().enumerate() {
+            let n = i + 1;
+            if p.id.trim().is_empty() {
+                return Err(format!("第 {n} 个项目缺少项目编号"));
+            }
+            if p.name.trim().is_empty() {
+                return Err(format!("第 {n} 个项目缺少项目名称"));
+            }
+            if p.units.iter().all(|u| u.trim().is_empty()) {
+                return Err(format!("项目“{}”至少要有一个单位", p.name));
+            }
+            if p.reaction_mode.trim().is_empty() {
+                return Err(format!("项目“{}”缺少反应模式", p.name));
+            }
+            if let Some(other) = self.projects[..i].iter().find(|o| o.id == p.id) {
+                return Err(format!(
+                    "项目编号 {} 重复：“{}”与“{}”",
+                    p.id, other.name, p.name
+                ));
+            }
+            if self.projects[..i].iter().any(|o| o.name == p.name) {
+                return Err(format!("项目名称“{}”重复", p.name));
+            }
+        }
+        for (i, c) in self.consumables.iter().enumerate() {
+            if c.name.trim().is_empty() {
+                return Err(format!("第 {} 个耗材缺少名称", i + 1));
+            }
+            if self.consumables[..i].iter().any(|o| o.name == c.name) {
+                return Err(format!("耗材名称“{}”重复", c.name));
+            }
+        }
+        let names = self.projects.iter().map(|p| &p.name);
+        let names: Vec<&String> = names
+            .chain(self.consumables.iter().map(|c| &c.name))
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            let ascii = self.instrument_name(name);
+            if ascii.trim().is_empty() {
+                return Err(format!("“{name}”的仪器名称为空"));
+            }
+            let legacy = i >= self.projects.len() && !self.transliterations.contains_key(*name);
+            if (!ascii.is_ascii() && !legacy) || ascii.contains(';') {
+                return Err(format!(
+                    "“{name}”的仪器名称“{ascii}”只能包含 ASCII 字符且不能含“;”，请填写仪器名称"
+                ));
+            }
+            if let Some(other) = names[..i].iter().find(|o| self.instrument_name(o) == ascii) {
+                return Err(format!("“{other}”与“{name}”的仪器名称都是“{ascii}”"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate, then write `Setting/project.json` in the current layout.
+    pub fn save(&self) -> Result<(), String> {
+        self.validate()?;
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let _ = std::fs::create_dir_all("Setting");
+        std::fs::write(PROJECT_PATH, data).map_err(|e| format!("保存项目配置失败: {e}"))
+    }
+}
+
+/// Read and parse a JSON settings file; `None` when it does not exist. A
+/// file that cannot be parsed is an error naming the line and column.
+pub fn read_json<T: DeserializeOwned>(path: &str) -> Result<Option<T>, String> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("读取 {path} 失败: {e}")),
+    };
+    serde_json::from_str(data.trim_start_matches('\u{feff}'))
+        .map(Some)
+        .map_err(|e| {
+            // serde_json appends " at line L column C"; report it in Chinese instead.
+            let msg = e.to_string();
+            let msg = msg.split(" at line ").next().unwrap_or_default();
+            format!("{path} 第 {} 行第 {} 列有误: {msg}", e.line(), e.column())
+        })
+}
+
+/// Load `Setting/project.json`; the built-in catalog when it does not exist.
+pub fn load_project_config() -> Result<ProjectConfig, String> {
+    Ok(read_json(PROJECT_PATH)?.unwrap_or_default())
+}
+
+/// Move an unreadable `Setting/project.json` aside as
+/// `Setting/project_<时间>.json.bak`, so the catalog can be saved afresh
+/// without losing the original. Returns the backup path.
+pub fn discard_project_config() -> Result<String, String> {
+    let stamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
+    let backup = format!("Setting/project_{stamp}.json.bak");
+    std::fs::rename(PROJECT_PATH, &backup).map_err(|e| format!("备份 {PROJECT_PATH} 失败: {e}"))?;
+    Ok(backup)
+}
+
+/// Label font settings from `Setting/font.json`, e.g. `{"path": "C:/Windows/Fonts/simhei.ttf"}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FontConfig {
+    pub path: Option<String>,
+}
+
+pub fn load_font_config() -> FontConfig {
+    std::fs::read_to_string("Setting/font.json")
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Export settings from `Setting/export.json`, e.g. `{"dpi": 203}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportConfig {
+    /// Printer resolution PNG and ZPL output is laid out for.
+    pub dpi: u32,
+    /// Index into [`EXPORT_FORMATS`](rust_learning::mono::EXPORT_FORMATS):
+    /// 8-bit PNG, 1-bit PNG or 1-bit BMP.
+    #[serde(default)]
+    pub format_index: usize,
+    /// Gray level below which a pixel turns black in 1-bit output.
+    #[serde(default = "default_threshold")]
+    pub threshold: u8,
+    #[serde(default)]
+    pub dither: bool,
+}
+
+fn default_threshold() -> u8 {
+    MonoOptions::default().threshold
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            dpi: 300,
+            format_index: 0,
+            threshold: default_threshold(),
+            dither: false,
+        }
+    }
+}
+
+impl ExportConfig {
+    pub fn mono_options(&self) -> MonoOptions {
+        MonoOptions {
+            threshold: self.threshold,
+            dither: self.dither,
+        }
+    }
+}
+
+pub fn load_export_config() -> ExportConfig {
+    std::fs::read_to_string("Setting/export.json")
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_export_config(cfg: &ExportConfig) -> Result<(), String> {
+    let data = serde_json::to_string_pretty(cfg).map_err(|e| e.to_string())?;
+    let _ = std::fs::create_dir_all("Setting");
+    std::fs::write("Setting/export.json", data).map_err(|e| format!("保存导出设置失败: {e}"))
 }
@@ -1,159 +1,405 @@
+use crate::config::ProjectConfig;
+use crate::keystore::{KeyMaterial, Keystore, LEGACY_KEY_ID};
+use crate::payload::{
+    CalibrationPayload, ConsumablePayload, Payload, QcPayload, ReagentPayload, SEPARATOR,
+};
 use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
 
 type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
 type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+type HmacSha256 = Hmac<Sha256>;
 
-const KEY: &[u8; 32] = b"12345678123456781234567812345678";
-const IV: &[u8; 16] = &[
-    0x1f, 0x32, 0x43, 0x51, 0x56, 0x98, 0xaf, 0xed, 0xab, 0xc8, 0x21, 0x45, 0x63, 0x72, 0xac, 0xfc,
-];
+/// Prefix of the current authenticated envelope format.
+const ENVELOPE_V2: &str = "RL2";
+const NONCE_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+
+/// Encrypt with the keystore's active key into a version 2 envelope:
+///
+/// `RL2.{key_id}.{base64(nonce || ciphertext || tag)}`
+///
+/// The nonce is a fresh random CBC IV and the tag is HMAC-SHA256 (truncated to
+/// 128 bits) over the header, nonce and ciphertext, so bit flips, truncation
+/// and key-ID swaps are all rejected by [`decrypt`].
+pub fn encrypt(ks: &Keystore, plaintext: &str) -> Result<String, String> {
+    let km = ks.active();
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill_bytes(&mut nonce);
 
-pub fn encrypt(plaintext: &str) -> Result<String, String> {
     let pt = plaintext.as_bytes();
     let mut buf = vec![0u8; pt.len() + 32]; // extra for padding
     buf[..pt.len()].copy_from_slice(pt);
-    let encrypted = Aes256CbcEnc::new(KEY.into(), IV.into())
+    let encrypted = Aes256CbcEnc::new(&km.key.into(), &nonce.into())
         .encrypt_padded_mut::<Pkcs7>(&mut buf, pt.len())
         .map_err(|e| format!("AES加密失败: {e}"))?;
-    Ok(base64::Engine::encode(
-        &base64::engine::general_purpose::STANDARD,
-        encrypted,
-    ))
+
+    let header = format!("{ENVELOPE_V2}.{}", km.id);
+    let mut body = nonce.to_vec();
+    body.extend_from_slice(encrypted);
+    let tag = envelope_mac(&km.key, &header, &body)
+        .finalize()
+        .into_bytes();
+    body.extend_from_slice(&tag[..TAG_LEN]);
+    Ok(format!("{header}.{}", STANDARD.encode(body)))
+}
+
+/// Decrypt any payload format ever issued.
+///
+/// - `RL2.{key_id}.{base64}`: authenticated envelope, tag checked first
+/// - `{key_id}:{base64}`: unauthenticated CBC with the key's fixed IV
+/// - bare base64: unauthenticated CBC with the legacy key
+pub fn decrypt(ks: &Keystore, input: &str) -> Result<String, String> {
+    let input = input.trim();
+    if let Some(rest) = input
+        .strip_prefix(ENVELOPE_V2)
+        .and_then(|r| r.strip_prefix('.'))
+    {
+        let (key_id, b64) = rest.split_once('.').ok_or(TAMPERED)?;
+        let km = lookup(ks, key_id)?;
+        let body = STANDARD.decode(b64).map_err(|_| TAMPERED)?;
+        if body.len() < NONCE_LEN + TAG_LEN {
+            return Err(TAMPERED.into());
+        }
+        let (data, tag) = body.split_at(body.len() - TAG_LEN);
+        envelope_mac(&km.key, &format!("{ENVELOPE_V2}.{key_id}"), data)
+            .verify_truncated_left(tag)
+            .map_err(|_| TAMPERED)?;
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().map_err(|_| TAMPERED)?;
+        return cbc_decrypt(&km.key, &nonce, ciphertext.to_vec());
+    }
+
+    let (key_id, b64_input) = input.split_once(':').unwrap_or((LEGACY_KEY_ID, input));
+    let km = lookup(ks, key_id)?;
+    let ciphertext = STANDARD
+        .decode(b64_input.trim())
+        .map_err(|e| format!("Base64解码失败: {e}"))?;
+    cbc_decrypt(&km.key, &km.iv, ciphertext)
+}
+
+const TAMPERED: &str = "条码校验失败：内容被篡改或不完整";
+
+fn lookup<'a>(ks: &'a Keystore, key_id: &str) -> Result<&'a KeyMaterial, String> {
+    ks.get(key_id)
+        .ok_or_else(|| format!("未知密钥ID: {key_id}（请先解锁密钥库）"))
+}
+
+fn envelope_mac(key: &[u8; 32], header: &str, data: &[u8]) -> HmacSha256 {
+    // Separate MAC key so the AES key is never used directly with HMAC.
+    let mac_key = Sha256::new()
+        .chain_update(b"rl-clia-mac")
+        .chain_update(key)
+        .finalize();
+    let mut mac = HmacSha256::new_from_slice(&mac_key).expect("HMAC accepts any key length");
+    mac.update(header.as_bytes());
+    mac.update(data);
+    mac
 }
 
-pub fn decrypt(b64_input: &str) -> Result<String, String> {
-    let ciphertext =
-        base64::Engine::decode(&base64::engine::general_purpose::STANDARD, b64_input.trim())
-            .map_err(|e| format!("Base64解码失败: {e}"))?;
+fn cbc_decrypt(key: &[u8; 32], iv: &[u8; 16], ciphertext: Vec<u8>) -> Result<String, String> {
     // Use exact-sized buffer so decrypt_padded_mut only processes ciphertext
     let mut buf = ciphertext;
-    let decrypted = Aes256CbcDec::new(KEY.into(), IV.into())
+    let decrypted = Aes256CbcDec::new(key.into(), iv.into())
         .decrypt_padded_mut::<Pkcs7>(&mut buf)
         .map_err(|e| format!("AES解密失败: {e}"))?;
     String::from_utf8(decrypted.to_vec()).map_err(|e| format!("编码错误: {e}"))
 }
 
-fn replace_beta(name: &str) -> &str {
-    match name {
-        "S100β" => "S100B",
-        "Aβ1-42" => "AB1-42",
-        "β-HCG" => "B-HCG",
-        _ => name,
+/// Write the catalog name as the instrument's ASCII name, check that every
+/// field is one the instrument can read, then encrypt. A consumable without
+/// an instrument name keeps its catalog name as it always has been written.
+fn compose(ks: &Keystore, proj: &ProjectConfig, mut payload: Payload) -> Result<String, String> {
+    let consumable = matches!(payload, Payload::Consumable(_));
+    let name = payload.name_mut();
+    let legacy = consumable && !proj.transliterations.contains_key(name.as_str());
+    *name = proj.instrument_name(name).to_string();
+    if !name.is_ascii() && !legacy {
+        return Err(format!("“{name}”没有 ASCII 仪器名称，请在项目管理中填写"));
     }
+    for (i, (label, value)) in payload.fields().into_iter().enumerate() {
+        // The name comes first; a legacy consumable name may be non-ASCII.
+        if !value.is_ascii() && (!legacy || i > 0) {
+            return Err(format!("{label}“{value}”含非 ASCII 字符，仪器无法识别"));
+        }
+        check_field(label, &value)?;
+    }
+    let wire = payload.to_wire();
+    if Payload::from_wire(&wire).as_ref() != Ok(&payload) {
+        return Err(format!("条码内容无法按仪器格式还原: {wire}"));
+    }
+    encrypt(ks, &wire)
+}
+
+/// Reject a value the instrument cannot read back in its own field. There is
+/// no escape syntax, so `;` would shift every later field, and a line break
+/// ends the scan early on keyboard-wedge readers.
+fn check_field(label: &str, value: &str) -> Result<(), String> {
+    if value.contains(SEPARATOR) {
+        return Err(format!("{label}“{value}”不能包含分隔符“{SEPARATOR}”"));
+    }
+    if value.chars().any(|c| c.is_ascii_control()) {
+        return Err(format!(
+            "{label}“{}”不能包含换行等控制字符",
+            value.escape_default()
+        ));
+    }
+    Ok(())
 }
 
 pub fn compose_reagent(
-    project_name: &str,
-    project_id: &str,
-    lot: &str,
-    prod_date: &str,
-    expire_date: &str,
-    test_counts: &str,
-    open_days: &str,
-    reaction_mode: &str,
-    serial_number: &str,
-    unit: &str,
-    top_param: &str,
-    hs_param: &str,
-    logec_param: &str,
-    bottom_param: &str,
-    range_low: &str,
-    range_upper: &str,
-    low_limit: &str,
-    upper_limit: &str,
+    ks: &Keystore,
+    proj: &ProjectConfig,
+    payload: &ReagentPayload,
 ) -> Result<String, String> {
-    let s = format!(
-        "reagent;{};{};{};{};{};{};{};{};{};{};{};{};{};{};{};{};{};{}",
-        replace_beta(project_name),
-        project_id,
-        lot,
-        prod_date,
-        expire_date,
-        test_counts,
-        open_days,
-        reaction_mode,
-        serial_number,
-        unit,
-        top_param,
-        hs_param,
-        logec_param,
-        bottom_param,
-        range_low,
-        range_upper,
-        low_limit,
-        upper_limit,
-    );
-    encrypt(&s)
+    compose(ks, proj, Payload::Reagent(payload.clone()))
 }
 
 pub fn compose_calibration(
-    project_name: &str,
-    project_id: &str,
-    lot: &str,
-    prod_date: &str,
-    expire_date: &str,
-    reaction_mode: &str,
-    c1: &str,
-    c2: &str,
+    ks: &Keystore,
+    proj: &ProjectConfig,
+    payload: &CalibrationPayload,
 ) -> Result<String, String> {
-    let s = format!(
-        "calibration;{};{};{};{};{};{};{};{}",
-        replace_beta(project_name),
-        project_id,
-        lot,
-        prod_date,
-        expire_date,
-        reaction_mode,
-        c1,
-        c2,
-    );
-    encrypt(&s)
+    compose(ks, proj, Payload::Calibration(payload.clone()))
 }
 
 pub fn compose_consumable(
-    project_name: &str,
-    lot: &str,
-    prod_date: &str,
-    expire_date: &str,
-    test_counts: &str,
-    open_days: &str,
+    ks: &Keystore,
+    proj: &ProjectConfig,
+    payload: &ConsumablePayload,
 ) -> Result<String, String> {
-    let s = format!(
-        "consumable;{};{};{};{};{};{}",
-        replace_beta(project_name),
-        lot,
-        prod_date,
-        expire_date,
-        test_counts,
-        open_days,
-    );
-    encrypt(&s)
+    compose(ks, proj, Payload::Consumable(payload.clone()))
 }
 
 pub fn compose_quality(
-    project_name: &str,
-    project_id: &str,
-    lot: &str,
-    prod_date: &str,
-    expire_date: &str,
-    reaction_mode: &str,
-    q1: &str,
-    sd1: &str,
-    q2: &str,
-    sd2: &str,
+    ks: &Keystore,
+    proj: &ProjectConfig,
+    payload: &QcPayload,
 ) -> Result<String, String> {
-    let s = format!(
-        "qc;{};{};{};{};{};{};{};{};{};{}",
-        replace_beta(project_name),
-        project_id,
-        lot,
-        prod_date,
-        expire_date,
-        reaction_mode,
-        q1,
-        sd1,
-        q2,
-        sd2,
-    );
-    encrypt(&s)
+    compose(ks, proj, Payload::Qc(payload.clone()))
+}
+
+/// Parse a decrypted plaintext, mapping the instrument name back to the
+/// catalog name it was composed from.
+pub fn parse(proj: &ProjectConfig, plain: &str) -> Result<Payload, String> {
+    let mut payload = Payload::from_wire(plain)?;
+    let name = payload.name_mut();
+    *name = proj.catalog_name(name).to_string();
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(payload: Payload) {
+        let ks = Keystore::default();
+        let proj = ProjectConfig::default();
+        let enc = match &payload {
+            Payload::Reagent(p) => compose_reagent(&ks, &proj, p),
+            Payload::Calibration(p) => compose_calibration(&ks, &proj, p),
+            Payload::Consumable(p) => compose_consumable(&ks, &proj, p),
+            Payload::Qc(p) => compose_quality(&ks, &proj, p),
+        }
+        .unwrap();
+        let plain = decrypt(&ks, &enc).unwrap();
+        if !matches!(payload, Payload::Consumable(_)) {
+            assert!(plain.is_ascii(), "{plain}");
+        }
+        assert_eq!(parse(&proj, &plain).unwrap(), payload);
+    }
+
+    fn reagent() -> ReagentPayload {
+        ReagentPayload {
+            project_name: "S100β".into(),
+            project_id: "10".into(),
+            lot: "123456".into(),
+            prod_date: "2026-01-01".into(),
+            expire_date: "2027-01-01".into(),
+            test_counts: "50".into(),
+            open_days: "28".into(),
+            reaction_mode: "1".into(),
+            serial_number: "202601010001".into(),
+            unit: "pg/mL".into(),
+            curve_a: "1.5".into(),
+            curve_b: "-2".into(),
+            curve_c: "3e4".into(),
+            curve_d: "0.25".into(),
+            range_low: "0".into(),
+            range_upper: "100".into(),
+            limit_low: "".into(),
+            limit_upper: "50".into(),
+        }
+    }
+
+    #[test]
+    fn compose_then_parse_is_identity_for_every_type() {
+        roundtrip(Payload::Reagent(reagent()));
+        roundtrip(Payload::Calibration(CalibrationPayload {
+            project_name: "cTnI".into(),
+            project_id: "1".into(),
+            lot: "C1".into(),
+            prod_date: "2026-01-01".into(),
+            expire_date: "2026-07-01".into(),
+            reaction_mode: "1".into(),
+            c1: "1000".into(),
+            c2: "20000".into(),
+        }));
+        roundtrip(Payload::Consumable(ConsumablePayload {
+            name: "激发液B".into(),
+            lot: "F1".into(),
+            prod_date: "2026-01-01".into(),
+            expire_date: "2027-01-01".into(),
+            test_counts: "2000".into(),
+            open_days: "30".into(),
+        }));
+        roundtrip(Payload::Qc(QcPayload {
+            project_name: "β-HCG".into(),
+            project_id: "24".into(),
+            lot: "Q1".into(),
+            prod_date: "2026-01-01".into(),
+            expire_date: "2026-12-31".into(),
+            reaction_mode: "1".into(),
+            q1: "5.2".into(),
+            sd1: "0.4".into(),
+            q2: "50".into(),
+            sd2: "3.1".into(),
+        }));
+    }
+
+    #[test]
+    fn separator_is_rejected() {
+        let ks = Keystore::default();
+        let proj = ProjectConfig::default();
+        for set in [
+            (|r: &mut ReagentPayload| r.project_name = "A;B".into()) as fn(&mut ReagentPayload),
+            |r| r.lot = "12;34".into(),
+            |r| r.unit = "pg;mL".into(),
+            |r| r.limit_upper = ";".into(),
+        ] {
+            let mut r = reagent();
+            set(&mut r);
+            let err = compose_reagent(&ks, &proj, &r).unwrap_err();
+            assert!(err.contains("分隔符"), "{err}");
+        }
+        let c = ConsumablePayload {
+            name: "激发液A".into(),
+            lot: "F;1".into(),
+            ..Default::default()
+        };
+        assert!(compose_consumable(&ks, &proj, &c)
+            .unwrap_err()
+            .contains("分隔符"));
+    }
+
+    #[test]
+    fn default_consumable_payload_matches_baseline_bytes() {
+        let ks = Keystore::default();
+        let proj = ProjectConfig::default();
+        for name in ["激发液A", "激发液B"] {
+            let c = ConsumablePayload {
+                name: name.into(),
+                lot: "F1".into(),
+                prod_date: "2026-01-01".into(),
+                expire_date: "2027-01-01".into(),
+                test_counts: "2000".into(),
+                open_days: "30".into(),
+            };
+            let enc = compose_consumable(&ks, &proj, &c).unwrap();
+            let baseline = format!("consumable;{name};F1;2026-01-01;2027-01-01;2000;30");
+            assert_eq!(decrypt(&ks, &enc).unwrap().as_bytes(), baseline.as_bytes());
+        }
+    }
+
+    #[test]
+    fn non_ascii_project_name_is_flagged_not_renamed() {
+        let ks = Keystore::default();
+        let proj = ProjectConfig::default();
+        let r = ReagentPayload {
+            project_name: "β2-MG".into(),
+            ..reagent()
+        };
+        let err = compose_reagent(&ks, &proj, &r).unwrap_err();
+        assert!(err.contains("β2-MG") && err.contains("ASCII"), "{err}");
+    }
+
+    #[test]
+    fn instrument_split_detects_shifted_fields() {
+        let mut wire = Payload::Reagent(reagent()).to_wire();
+        wire = wire.replacen("123456", "12;3456", 1);
+        assert!(Payload::from_wire(&wire)
+            .unwrap_err()
+            .contains("字段数量错误"));
+    }
+
+    #[test]
+    fn control_and_non_ascii_characters_are_rejected() {
+        let ks = Keystore::default();
+        let proj = ProjectConfig::default();
+        let lot = ReagentPayload {
+            lot: "12\n34".into(),
+            ..reagent()
+        };
+        assert!(compose_reagent(&ks, &proj, &lot)
+            .unwrap_err()
+            .contains("控制字符"));
+        let unit = ReagentPayload {
+            unit: "μg/L".into(),
+            ..reagent()
+        };
+        assert!(compose_reagent(&ks, &proj, &unit)
+            .unwrap_err()
+            .contains("ASCII"));
+    }
+
+    /// Re-encode the body of an RL2 envelope after `edit` changes it.
+    fn edit_body(enc: &str, edit: impl FnOnce(&mut Vec<u8>)) -> String {
+        let (header, b64) = enc.rsplit_once('.').unwrap();
+        let mut body = STANDARD.decode(b64).unwrap();
+        edit(&mut body);
+        format!("{header}.{}", STANDARD.encode(body))
+    }
+
+    #[test]
+    fn tampered_envelopes_are_rejected() {
+        let mut ks = Keystore::default();
+        let id = ks.rotate();
+        let enc = encrypt(&ks, "reagent;cTnI;1").unwrap();
+        assert!(enc.starts_with(&format!("{ENVELOPE_V2}.{id}.")));
+        assert_eq!(decrypt(&ks, &enc).unwrap(), "reagent;cTnI;1");
+
+        let bad_tag = edit_body(&enc, |b| *b.last_mut().unwrap() ^= 1);
+        let bad_ciphertext = edit_body(&enc, |b| b[NONCE_LEN] ^= 0x80);
+        let bad_nonce = edit_body(&enc, |b| b[0] ^= 1);
+        let truncated = edit_body(&enc, |b| b.truncate(b.len() - 1));
+        let too_short = edit_body(&enc, |b| b.truncate(NONCE_LEN + TAG_LEN - 1));
+        let swapped_key = enc.replacen(&id, LEGACY_KEY_ID, 1);
+        for bad in [
+            bad_tag,
+            bad_ciphertext,
+            bad_nonce,
+            truncated,
+            too_short,
+            swapped_key,
+        ] {
+            assert_eq!(decrypt(&ks, &bad).unwrap_err(), TAMPERED, "{bad}");
+        }
+        assert!(decrypt(&ks, &enc[..enc.len() - 4]).is_err());
+        assert!(decrypt(&ks, &format!("{ENVELOPE_V2}.{id}"))
+            .unwrap_err()
+            .contains("篡改"));
+    }
+
+    #[test]
+    fn unknown_key_id_is_rejected() {
+        let mut ks = Keystore::default();
+        let id = ks.rotate();
+        let enc = encrypt(&ks, "qc;x").unwrap();
+        let renamed = enc.replacen(&id, "k00000000000000", 1);
+        assert!(decrypt(&ks, &renamed).unwrap_err().contains("未知密钥ID"));
+    }
 }
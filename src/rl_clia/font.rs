@@ -0,0 +1,90 @@
+//! Fonts for label text.
+//!
+//! Candidates are tried in order: the file named in `Setting/font.json`, the
+//! usual system fonts, then the Noto Sans SC font that build.rs bundles into
+//! the binary (the build fails without it). Each text line
+//! is drawn with the first font that has a glyph for every character in it;
+//! if none does, rendering fails rather than leaving the line blank.
+
+use crate::config;
+use ab_glyph::{Font, FontArc, FontRef};
+use std::sync::OnceLock;
+
+/// A loaded font and its raw file data, kept for PDF embedding.
+pub struct LabelFont {
+    pub data: &'static [u8],
+    pub font: FontArc,
+}
+
+impl LabelFont {
+    /// Fonts are loaded once and live for the whole run, so the data is leaked
+    /// to let [`FontRef`] borrow it.
+    fn from_vec(data: Vec<u8>) -> Result<LabelFont, ab_glyph::InvalidFont> {
+        LabelFont::from_static(Box::leak(data.into_boxed_slice()))
+    }
+
+    fn from_static(data: &'static [u8]) -> Result<LabelFont, ab_glyph::InvalidFont> {
+        let font = FontArc::new(FontRef::try_from_slice(data)?);
+        Ok(LabelFont { data, font })
+    }
+}
+
+const BUNDLED_FONT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/NotoSansSC-Regular.otf"));
+
+fn system_candidates() -> &'static [&'static str] {
+    if cfg!(target_os = "windows") {
+        &[
+            "C:/Windows/Fonts/simhei.ttf",
+            "C:/Windows/Fonts/msyh.ttf",
+            "C:/Windows/Fonts/arial.ttf",
+            "C:/Windows/Fonts/times.ttf",
+        ]
+    } else if cfg!(target_os = "macos") {
+        &[
+            "/System/Library/Fonts/STHeiti Light.ttc",
+            "/System/Library/Fonts/Helvetica.ttc",
+        ]
+    } else {
+        &[
+            "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+            "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+            "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+        ]
+    }
+}
+
+fn load_all() -> Result<Vec<LabelFont>, String> {
+    let mut fonts = Vec::new();
+    if let Some(path) = config::load_font_config().path {
+        let data = std::fs::read(&path).map_err(|e| format!("读取字体文件 {path} 失败: {e}"))?;
+        let font = LabelFont::from_vec(data).map_err(|e| format!("字体文件 {path} 无效: {e}"))?;
+        fonts.push(font);
+    }
+    for path in system_candidates() {
+        if let Ok(data) = std::fs::read(path)
+            && let Ok(font) = LabelFont::from_vec(data)
+        {
+            fonts.push(font);
+        }
+    }
+    fonts.push(LabelFont::from_static(BUNDLED_FONT).map_err(|e| format!("内置字体无效: {e}"))?);
+    Ok(fonts)
+}
+
+/// Pick a font that can draw every character of `text`.
+pub fn font_for(text: &str) -> Result<&'static LabelFont, String> {
+    static FONTS: OnceLock<Result<Vec<LabelFont>, String>> = OnceLock::new();
+    let fonts = FONTS.get_or_init(load_all).as_ref().map_err(Clone::clone)?;
+    let covers = |f: &LabelFont| {
+        text.chars()
+            .filter(|c| !c.is_whitespace())
+            .all(|c| f.font.glyph_id(c).0 != 0)
+    };
+    fonts.iter().find(|f| covers(f)).ok_or_else(|| {
+        let missing: String = text
+            .chars()
+            .filter(|c| !c.is_whitespace() && fonts.iter().all(|f| f.font.glyph_id(*c).0 == 0))
+            .collect();
+        format!("字体缺少字符「{missing}」，无法绘制标签文字，请在 Setting/font.json 中指定支持中文的字体")
+    })
+}
@@ -0,0 +1,253 @@
+//! Passphrase-protected AES key material for RL-CLIA payloads.
+//!
+//! Keys live in `Setting/keystore.json`, whose body is AES-256-CBC encrypted
+//! with a PBKDF2-SHA256 key derived from the operator's passphrase. Every key
+//! has an ID that is written into the barcode, so old labels keep decrypting
+//! after the active key is rotated. The original hardcoded key is always
+//! available under [`LEGACY_KEY_ID`].
+
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+pub const KEYSTORE_PATH: &str = "Setting/keystore.json";
+pub const LEGACY_KEY_ID: &str = "legacy";
+
+const LEGACY_KEY: &[u8; 32] = b"12345678123456781234567812345678";
+const LEGACY_IV: &[u8; 16] = &[
+    0x1f, 0x32, 0x43, 0x51, 0x56, 0x98, 0xaf, 0xed, 0xab, 0xc8, 0x21, 0x45, 0x63, 0x72, 0xac, 0xfc,
+];
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+#[derive(Clone)]
+pub struct KeyMaterial {
+    pub id: String,
+    pub key: [u8; 32],
+    pub iv: [u8; 16],
+}
+
+/// Only the key ID is shown, so `{:?}` and panic messages never leak key bytes.
+impl std::fmt::Debug for KeyMaterial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyMaterial")
+            .field("id", &self.id)
+            .field("key", &"<redacted>")
+            .field("iv", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    keys: Vec<KeyMaterial>,
+    active: String,
+}
+
+/// On-disk container: everything except the KDF salt is encrypted.
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    salt: String,
+    iv: String,
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreBody {
+    active: String,
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyEntry {
+    id: String,
+    key: String,
+    iv: String,
+}
+
+impl Default for Keystore {
+    fn default() -> Self {
+        Keystore {
+            keys: vec![KeyMaterial {
+                id: LEGACY_KEY_ID.into(),
+                key: *LEGACY_KEY,
+                iv: *LEGACY_IV,
+            }],
+            active: LEGACY_KEY_ID.into(),
+        }
+    }
+}
+
+impl Keystore {
+    pub fn get(&self, id: &str) -> Option<&KeyMaterial> {
+        self.keys.iter().find(|k| k.id == id)
+    }
+
+    /// Key used for newly composed payloads.
+    pub fn active(&self) -> &KeyMaterial {
+        self.get(&self.active)
+            .or_else(|| self.get(LEGACY_KEY_ID))
+            .expect("legacy key is always present")
+    }
+
+    /// Generate a fresh random key, make it active and return its ID. Keys
+    /// are never removed, so a second rotation within the same second gets a
+    /// numbered ID rather than replacing the first.
+    pub fn rotate(&mut self) -> String {
+        let stamp = format!("k{}", chrono::Local::now().format("%Y%m%d%H%M%S"));
+        let id = std::iter::once(stamp.clone())
+            .chain((2..).map(|n| format!("{stamp}-{n}")))
+            .find(|id| self.get(id).is_none())
+            .expect("unbounded candidate IDs");
+        let mut key = [0u8; 32];
+        let mut iv = [0u8; 16];
+        rand::rng().fill_bytes(&mut key);
+        rand::rng().fill_bytes(&mut iv);
+        self.keys.push(KeyMaterial {
+            id: id.clone(),
+            key,
+            iv,
+        });
+        self.active = id.clone();
+        id
+    }
+
+    pub fn load(path: impl AsRef<Path>, passphrase: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("读取密钥库失败: {e}"))?;
+        let file: KeystoreFile =
+            serde_json::from_str(&text).map_err(|e| format!("密钥库格式错误: {e}"))?;
+        let salt = STANDARD
+            .decode(&file.salt)
+            .map_err(|e| format!("密钥库格式错误: {e}"))?;
+        let iv: [u8; 16] = decode_fixed(&file.iv)?;
+        let mut buf = STANDARD
+            .decode(&file.data)
+            .map_err(|e| format!("密钥库格式错误: {e}"))?;
+        let plain = Aes256CbcDec::new(&derive_key(passphrase, &salt).into(), &iv.into())
+            .decrypt_padded_mut::<Pkcs7>(&mut buf)
+            .map_err(|_| "密码错误或密钥库已损坏".to_string())?;
+        let body: KeystoreBody =
+            serde_json::from_slice(plain).map_err(|_| "密码错误或密钥库已损坏".to_string())?;
+
+        let mut ks = Keystore::default();
+        for entry in body.keys {
+            if entry.id == LEGACY_KEY_ID {
+                continue;
+            }
+            ks.keys.push(KeyMaterial {
+                key: decode_fixed(&entry.key)?,
+                iv: decode_fixed(&entry.iv)?,
+                id: entry.id,
+            });
+        }
+        if ks.get(&body.active).is_none() {
+            return Err(format!("密钥库中不存在当前密钥: {}", body.active));
+        }
+        ks.active = body.active;
+        Ok(ks)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, passphrase: &str) -> Result<(), String> {
+        if passphrase.is_empty() {
+            return Err("密钥库密码不能为空".into());
+        }
+        let body = KeystoreBody {
+            active: self.active.clone(),
+            keys: self
+                .keys
+                .iter()
+                .filter(|k| k.id != LEGACY_KEY_ID)
+                .map(|k| KeyEntry {
+                    id: k.id.clone(),
+                    key: STANDARD.encode(k.key),
+                    iv: STANDARD.encode(k.iv),
+                })
+                .collect(),
+        };
+        let plain = serde_json::to_vec(&body).map_err(|e| e.to_string())?;
+
+        let mut salt = [0u8; 16];
+        let mut iv = [0u8; 16];
+        rand::rng().fill_bytes(&mut salt);
+        rand::rng().fill_bytes(&mut iv);
+        let mut buf = vec![0u8; plain.len() + 16];
+        buf[..plain.len()].copy_from_slice(&plain);
+        let encrypted = Aes256CbcEnc::new(&derive_key(passphrase, &salt).into(), &iv.into())
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, plain.len())
+            .map_err(|e| format!("密钥库加密失败: {e}"))?;
+
+        let file = KeystoreFile {
+            version: 1,
+            salt: STANDARD.encode(salt),
+            iv: STANDARD.encode(iv),
+            data: STANDARD.encode(encrypted),
+        };
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let text = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        // The file is the only copy of the keys: replace it atomically.
+        let tmp = path.with_extension("json.tmp");
+        std::fs::File::create(&tmp)
+            .and_then(|mut f| {
+                f.write_all(text.as_bytes())?;
+                f.sync_all()
+            })
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("保存密钥库失败: {e}"))
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+fn decode_fixed<const N: usize>(b64: &str) -> Result<[u8; N], String> {
+    STANDARD
+        .decode(b64)
+        .ok()
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| format!("密钥长度错误，应为 {N} 字节"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotating_twice_keeps_both_keys() {
+        let mut ks = Keystore::default();
+        let first = ks.rotate();
+        let second = ks.rotate();
+        assert_ne!(first, second);
+        assert_eq!(ks.active().id, second);
+        assert!(ks.get(&first).is_some());
+        assert!(ks.get(LEGACY_KEY_ID).is_some());
+    }
+
+    #[test]
+    fn save_replaces_the_file_and_reloads() {
+        let dir = std::env::temp_dir().join(format!("rlclia-ks-{}", std::process::id()));
+        let path = dir.join("keystore.json");
+        let mut ks = Keystore::default();
+        let first = ks.rotate();
+        ks.save(&path, "pw").unwrap();
+        let second = ks.rotate();
+        ks.save(&path, "pw").unwrap();
+        assert!(!path.with_extension("json.tmp").exists());
+        let loaded = Keystore::load(&path, "pw").unwrap();
+        assert_eq!(loaded.active().id, second);
+        assert_eq!(loaded.get(&first).unwrap().key, ks.get(&first).unwrap().key);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,9 +1,46 @@
 #![windows_subsystem = "windows"]
 mod app;
 mod barcode;
+mod batch;
 mod config;
 mod encryptor;
+mod font;
+mod keystore;
+mod payload;
+mod serials;
+mod sheet;
+mod template;
+mod validation;
+
+#[cfg(test)]
+#[path = "../../tests/support/golden.rs"]
+mod golden;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("batch") {
+        attach_console();
+        std::process::exit(app::run_batch(&args[1..]));
+    }
     app::run();
 }
+
+/// The windows subsystem starts without a console, so batch mode attaches to
+/// the one it was started from; otherwise its messages go nowhere. `cmd` does
+/// not wait for such programs: run `start /wait RL-CLIA batch ...` to read
+/// `%ERRORLEVEL%`.
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // Fails harmlessly when there is no parent console, e.g. from Explorer.
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
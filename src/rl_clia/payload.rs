@@ -0,0 +1,342 @@
+//! Typed RL-CLIA barcode payloads.
+//!
+//! The instrument reads a `;`-joined plaintext whose first field is the label
+//! type tag (`reagent` / `calibration` / `consumable` / `qc`) followed by a
+//! fixed, type-specific list of fields. Each payload struct below mirrors one
+//! of those layouts and round-trips to and from the wire string.
+//!
+//! The instrument has no escape syntax: it splits on every `;` and reads the
+//! fields by position. A field containing `;` therefore cannot be encoded at
+//! all, and the encryptor rejects it before composing.
+
+/// Field separator of the wire format.
+pub const SEPARATOR: char = ';';
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReagentPayload {
+    pub project_name: String,
+    pub project_id: String,
+    pub lot: String,
+    pub prod_date: String,
+    pub expire_date: String,
+    pub test_counts: String,
+    pub open_days: String,
+    pub reaction_mode: String,
+    pub serial_number: String,
+    pub unit: String,
+    pub curve_a: String,
+    pub curve_b: String,
+    pub curve_c: String,
+    pub curve_d: String,
+    pub range_low: String,
+    pub range_upper: String,
+    pub limit_low: String,
+    pub limit_upper: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CalibrationPayload {
+    pub project_name: String,
+    pub project_id: String,
+    pub lot: String,
+    pub prod_date: String,
+    pub expire_date: String,
+    pub reaction_mode: String,
+    pub c1: String,
+    pub c2: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConsumablePayload {
+    pub name: String,
+    pub lot: String,
+    pub prod_date: String,
+    pub expire_date: String,
+    pub test_counts: String,
+    pub open_days: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QcPayload {
+    pub project_name: String,
+    pub project_id: String,
+    pub lot: String,
+    pub prod_date: String,
+    pub expire_date: String,
+    pub reaction_mode: String,
+    pub q1: String,
+    pub sd1: String,
+    pub q2: String,
+    pub sd2: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    Reagent(ReagentPayload),
+    Calibration(CalibrationPayload),
+    Consumable(ConsumablePayload),
+    Qc(QcPayload),
+}
+
+/// Wire layout of one payload type: tag, display labels and field order.
+trait Record: Sized {
+    const TAG: &'static str;
+    const LABELS: &'static [&'static str];
+    fn values(&self) -> Vec<&str>;
+    fn from_values(v: &[&str]) -> Self;
+}
+
+impl Record for ReagentPayload {
+    const TAG: &'static str = "reagent";
+    const LABELS: &'static [&'static str] = &[
+        "项目名称",
+        "项目编号",
+        "试剂批号",
+        "生产日期",
+        "失效日期",
+        "测试/盒",
+        "开瓶天数",
+        "反应模式",
+        "序列号",
+        "结果单位",
+        "曲线参数a",
+        "曲线参数b",
+        "曲线参数c",
+        "曲线参数d",
+        "范围下限",
+        "范围上限",
+        "限值下限",
+        "限值上限",
+    ];
+    fn values(&self) -> Vec<&str> {
+        vec![
+            &self.project_name,
+            &self.project_id,
+            &self.lot,
+            &self.prod_date,
+            &self.expire_date,
+            &self.test_counts,
+            &self.open_days,
+            &self.reaction_mode,
+            &self.serial_number,
+            &self.unit,
+            &self.curve_a,
+            &self.curve_b,
+            &self.curve_c,
+            &self.curve_d,
+            &self.range_low,
+            &self.range_upper,
+            &self.limit_low,
+            &self.limit_upper,
+        ]
+    }
+    fn from_values(v: &[&str]) -> Self {
+        ReagentPayload {
+            project_name: v[0].into(),
+            project_id: v[1].into(),
+            lot: v[2].into(),
+            prod_date: v[3].into(),
+            expire_date: v[4].into(),
+            test_counts: v[5].into(),
+            open_days: v[6].into(),
+            reaction_mode: v[7].into(),
+            serial_number: v[8].into(),
+            unit: v[9].into(),
+            curve_a: v[10].into(),
+            curve_b: v[11].into(),
+            curve_c: v[12].into(),
+            curve_d: v[13].into(),
+            range_low: v[14].into(),
+            range_upper: v[15].into(),
+            limit_low: v[16].into(),
+            limit_upper: v[17].into(),
+        }
+    }
+}
+
+impl Record for CalibrationPayload {
+    const TAG: &'static str = "calibration";
+    const LABELS: &'static [&'static str] = &[
+        "项目名称",
+        "项目编号",
+        "校准批号",
+        "生产日期",
+        "失效日期",
+        "反应模式",
+        "C1发光值",
+        "C2发光值",
+    ];
+    fn values(&self) -> Vec<&str> {
+        vec![
+            &self.project_name,
+            &self.project_id,
+            &self.lot,
+            &self.prod_date,
+            &self.expire_date,
+            &self.reaction_mode,
+            &self.c1,
+            &self.c2,
+        ]
+    }
+    fn from_values(v: &[&str]) -> Self {
+        CalibrationPayload {
+            project_name: v[0].into(),
+            project_id: v[1].into(),
+            lot: v[2].into(),
+            prod_date: v[3].into(),
+            expire_date: v[4].into(),
+            reaction_mode: v[5].into(),
+            c1: v[6].into(),
+            c2: v[7].into(),
+        }
+    }
+}
+
+impl Record for ConsumablePayload {
+    const TAG: &'static str = "consumable";
+    const LABELS: &'static [&'static str] = &[
+        "耗材类型",
+        "耗材批号",
+        "生产日期",
+        "失效日期",
+        "可用频次",
+        "开瓶天数",
+    ];
+    fn values(&self) -> Vec<&str> {
+        vec![
+            &self.name,
+            &self.lot,
+            &self.prod_date,
+            &self.expire_date,
+            &self.test_counts,
+            &self.open_days,
+        ]
+    }
+    fn from_values(v: &[&str]) -> Self {
+        ConsumablePayload {
+            name: v[0].into(),
+            lot: v[1].into(),
+            prod_date: v[2].into(),
+            expire_date: v[3].into(),
+            test_counts: v[4].into(),
+            open_days: v[5].into(),
+        }
+    }
+}
+
+impl Record for QcPayload {
+    const TAG: &'static str = "qc";
+    const LABELS: &'static [&'static str] = &[
+        "项目名称",
+        "项目编号",
+        "质控批号",
+        "生产日期",
+        "失效日期",
+        "反应模式",
+        "Q1",
+        "SD1",
+        "Q2",
+        "SD2",
+    ];
+    fn values(&self) -> Vec<&str> {
+        vec![
+            &self.project_name,
+            &self.project_id,
+            &self.lot,
+            &self.prod_date,
+            &self.expire_date,
+            &self.reaction_mode,
+            &self.q1,
+            &self.sd1,
+            &self.q2,
+            &self.sd2,
+        ]
+    }
+    fn from_values(v: &[&str]) -> Self {
+        QcPayload {
+            project_name: v[0].into(),
+            project_id: v[1].into(),
+            lot: v[2].into(),
+            prod_date: v[3].into(),
+            expire_date: v[4].into(),
+            reaction_mode: v[5].into(),
+            q1: v[6].into(),
+            sd1: v[7].into(),
+            q2: v[8].into(),
+            sd2: v[9].into(),
+        }
+    }
+}
+
+fn join<R: Record>(r: &R) -> String {
+    let mut parts = vec![R::TAG];
+    parts.extend(r.values());
+    parts.join(&SEPARATOR.to_string())
+}
+
+fn split<R: Record>(fields: &[&str]) -> Result<R, String> {
+    if fields.len() != R::LABELS.len() {
+        return Err(format!(
+            "{} 字段数量错误: 应为 {}，实际 {}",
+            R::TAG,
+            R::LABELS.len(),
+            fields.len()
+        ));
+    }
+    Ok(R::from_values(fields))
+}
+
+fn labelled<R: Record>(r: &R) -> Vec<(&'static str, String)> {
+    R::LABELS
+        .iter()
+        .copied()
+        .zip(r.values().into_iter().map(str::to_string))
+        .collect()
+}
+
+impl Payload {
+    /// Serialize to the `;`-joined plaintext the instrument expects.
+    pub fn to_wire(&self) -> String {
+        match self {
+            Payload::Reagent(p) => join(p),
+            Payload::Calibration(p) => join(p),
+            Payload::Consumable(p) => join(p),
+            Payload::Qc(p) => join(p),
+        }
+    }
+
+    /// Parse a decrypted plaintext back into a typed payload, splitting it
+    /// the way the instrument does: on every `;`, with no escaping. A stray
+    /// `;` shows up as a wrong field count rather than shifted fields.
+    pub fn from_wire(plain: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = plain.split(SEPARATOR).collect();
+        let (tag, fields) = parts.split_first().ok_or("内容为空")?;
+        match *tag {
+            ReagentPayload::TAG => split(fields).map(Payload::Reagent),
+            CalibrationPayload::TAG => split(fields).map(Payload::Calibration),
+            ConsumablePayload::TAG => split(fields).map(Payload::Consumable),
+            QcPayload::TAG => split(fields).map(Payload::Qc),
+            other => Err(format!("未知条码类型: {other}")),
+        }
+    }
+
+    /// The project name, or the consumable type for a consumable label.
+    pub fn name_mut(&mut self) -> &mut String {
+        match self {
+            Payload::Reagent(p) => &mut p.project_name,
+            Payload::Calibration(p) => &mut p.project_name,
+            Payload::Consumable(p) => &mut p.name,
+            Payload::Qc(p) => &mut p.project_name,
+        }
+    }
+
+    /// Field label / value pairs for display, in wire order.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Payload::Reagent(p) => labelled(p),
+            Payload::Calibration(p) => labelled(p),
+            Payload::Consumable(p) => labelled(p),
+            Payload::Qc(p) => labelled(p),
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Persistent serial number allocation for reagent bottles.
+//!
+//! Serials are `{YYYYMMDD}{NNNN}`. The last number issued for each
+//! `(date, lot)` pair is kept in `Setting/serials.json`. A preview only peeks
+//! at the next numbers; an export reserves them under an exclusive lock on
+//! `Setting/serials.lock`, so other running instances wait, and commits them
+//! once its files are written. A serial is issued at most once, and only when
+//! it was actually exported.
+
+use chrono::{Local, NaiveDate};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+const SERIALS_PATH: &str = "Setting/serials.json";
+const MAX_PER_DAY: u32 = 9999;
+
+/// The next `count` serials for `lot` dated today, without issuing them.
+pub fn peek_serials(lot: &str, count: usize) -> Result<Vec<String>, String> {
+    peek_in(
+        Path::new(SERIALS_PATH),
+        Local::now().date_naive(),
+        lot,
+        count,
+    )
+}
+
+/// Lock the serial record and check that none of the `(lot, serial)` pairs
+/// has been issued since it was peeked.
+pub fn reserve(serials: &[(String, String)]) -> Result<Reservation, String> {
+    reserve_in(Path::new(SERIALS_PATH), serials)
+}
+
+/// Serials checked by [`reserve`]; the record stays locked until this is
+/// committed or dropped, and dropping it issues nothing.
+pub struct Reservation {
+    path: PathBuf,
+    issued: BTreeMap<String, u32>,
+    _lock: File,
+}
+
+impl Reservation {
+    /// Record the reserved serials as issued.
+    pub fn commit(self) -> Result<(), String> {
+        let tmp = self.path.with_extension("json.tmp");
+        let data = serde_json::to_string_pretty(&self.issued).map_err(|e| e.to_string())?;
+        std::fs::write(&tmp, data).map_err(|e| format!("保存序列号记录失败: {e}"))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| format!("保存序列号记录失败: {e}"))
+    }
+}
+
+fn key(date: &str, lot: &str) -> String {
+    format!("{date}|{}", lot.trim())
+}
+
+fn read(path: &Path) -> Result<BTreeMap<String, u32>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s).map_err(|e| format!("序列号记录损坏: {e}")),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("读取序列号记录失败: {e}")),
+    }
+}
+
+fn peek_in(path: &Path, date: NaiveDate, lot: &str, count: usize) -> Result<Vec<String>, String> {
+    let d = date.format("%Y%m%d").to_string();
+    let last = read(path)?.get(&key(&d, lot)).copied().unwrap_or(0);
+    let next = last as u64 + count as u64;
+    if next > MAX_PER_DAY as u64 {
+        return Err(format!(
+            "批号 {lot} 今日序列号已用至 {last}，无法再分配 {count} 个（上限 {MAX_PER_DAY}）"
+        ));
+    }
+    Ok((last + 1..=next as u32)
+        .map(|i| format!("{d}{i:04}"))
+        .collect())
+}
+
+fn reserve_in(path: &Path, serials: &[(String, String)]) -> Result<Reservation, String> {
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let lock = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension("lock"))
+        .and_then(|f| f.lock().map(|_| f))
+        .map_err(|e| format!("锁定序列号记录失败: {e}"))?;
+    let before = read(path)?;
+    let mut issued = before.clone();
+    for (lot, serial) in serials {
+        let (d, n) = serial
+            .split_at_checked(8)
+            .and_then(|(d, n)| Some((d, n.parse::<u32>().ok()?)))
+            .ok_or_else(|| format!("序列号格式错误: {serial}"))?;
+        let key = key(d, lot);
+        if n <= before.get(&key).copied().unwrap_or(0) {
+            return Err(format!(
+                "序列号 {serial}（批号 {lot}）已发放，请重新生成预览"
+            ));
+        }
+        let last = issued.entry(key).or_default();
+        *last = (*last).max(n);
+    }
+    Ok(Reservation {
+        path: path.to_path_buf(),
+        issued,
+        _lock: lock,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rl-serials-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("serials.json")
+    }
+
+    fn issue(path: &Path, date: NaiveDate, lot: &str, count: usize) -> Vec<String> {
+        let serials = peek_in(path, date, lot, count).unwrap();
+        let pairs: Vec<_> = serials
+            .iter()
+            .map(|s| (lot.to_string(), s.clone()))
+            .collect();
+        reserve_in(path, &pairs).unwrap().commit().unwrap();
+        serials
+    }
+
+    #[test]
+    fn allocation_continues_per_date_and_lot() {
+        let path = record("continue");
+        let day = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        assert_eq!(issue(&path, day, "L1", 2), ["202603010001", "202603010002"]);
+        assert_eq!(issue(&path, day, "L1", 1), ["202603010003"]);
+        assert_eq!(issue(&path, day, "L2", 1), ["202603010001"]);
+        assert_eq!(
+            issue(&path, day.succ_opt().unwrap(), "L1", 1),
+            ["202603020001"]
+        );
+        assert!(peek_in(&path, day, "L1", 9997).is_err());
+    }
+
+    #[test]
+    fn restart_continues_and_never_reissues() {
+        let path = record("restart");
+        let day = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        issue(&path, day, "L1", 3);
+
+        // A fresh process only has the file to go on.
+        let peeked = peek_in(&path, day, "L1", 1).unwrap();
+        assert_eq!(peeked, ["202603010004"]);
+
+        // An export that fails drops its reservation and issues nothing.
+        let pairs = vec![("L1".to_string(), peeked[0].clone())];
+        drop(reserve_in(&path, &pairs).unwrap());
+        assert_eq!(peek_in(&path, day, "L1", 1).unwrap(), peeked);
+
+        let old = vec![("L1".to_string(), "202603010002".to_string())];
+        assert!(reserve_in(&path, &old).is_err_and(|e| e.contains("已发放")));
+    }
+
+    #[test]
+    fn concurrent_reservations_of_the_same_serials_conflict() {
+        let path = record("lock");
+        let day = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        let serials = peek_in(&path, day, "L1", 2).unwrap();
+        let pairs: Vec<_> = serials
+            .iter()
+            .map(|s| ("L1".to_string(), s.clone()))
+            .collect();
+
+        let held = reserve_in(&path, &pairs).unwrap();
+        let other = {
+            let (path, pairs) = (path.clone(), pairs.clone());
+            std::thread::spawn(move || reserve_in(&path, &pairs).map(|_| ()))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(
+            !other.is_finished(),
+            "the lock must make the second export wait"
+        );
+        held.commit().unwrap();
+        assert!(other.join().unwrap().unwrap_err().contains("已发放"));
+    }
+}
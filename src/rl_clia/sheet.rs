@@ -0,0 +1,163 @@
+//! Label sheet layouts for PDF export.
+//!
+//! Sheets are read from `Setting/sheets.json`, all sizes in millimetres:
+//!
+//! ```json
+//! {
+//!   "offset_x": 0.0,
+//!   "offset_y": 0.0,
+//!   "sheets": {
+//!     "A4 3×4": { "page_width": 210, "page_height": 297, "rows": 4, "cols": 3,
+//!                 "cell_width": 55, "cell_height": 45, "gutter_x": 0, "gutter_y": 0,
+//!                 "margin_left": 10, "margin_top": 10 }
+//!   }
+//! }
+//! ```
+//!
+//! `offset_x` / `offset_y` shift every sheet to compensate for the printer's
+//! feed; positive values move labels right and down. The file is written with
+//! the built-in sheets on first run.
+
+use crate::config::read_json;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+const SHEETS_PATH: &str = "Setting/sheets.json";
+
+/// PDF points per millimetre.
+pub const PT_PER_MM: f32 = 72.0 / 25.4;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SheetTemplate {
+    pub page_width: f32,
+    pub page_height: f32,
+    pub rows: usize,
+    pub cols: usize,
+    pub cell_width: f32,
+    pub cell_height: f32,
+    #[serde(default)]
+    pub gutter_x: f32,
+    #[serde(default)]
+    pub gutter_y: f32,
+    pub margin_left: f32,
+    pub margin_top: f32,
+}
+
+impl SheetTemplate {
+    pub fn per_page(&self) -> usize {
+        self.rows * self.cols
+    }
+
+    /// Top-left corner of cell `slot` (row-major) in mm from the page's top-left.
+    pub fn cell_origin(&self, slot: usize) -> (f32, f32) {
+        let row = slot / self.cols;
+        let col = slot % self.cols;
+        (
+            self.margin_left + col as f32 * (self.cell_width + self.gutter_x),
+            self.margin_top + row as f32 * (self.cell_height + self.gutter_y),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SheetConfig {
+    #[serde(default)]
+    pub offset_x: f32,
+    #[serde(default)]
+    pub offset_y: f32,
+    pub sheets: BTreeMap<String, SheetTemplate>,
+}
+
+impl SheetConfig {
+    /// The built-in sheets with no printer offset.
+    pub fn builtin() -> SheetConfig {
+        SheetConfig {
+            offset_x: 0.0,
+            offset_y: 0.0,
+            sheets: builtin_sheets(),
+        }
+    }
+
+    /// Load `Setting/sheets.json`; the built-in sheets are always available.
+    /// A file that cannot be parsed is an error naming the line and column.
+    pub fn load() -> Result<SheetConfig, String> {
+        let mut cfg = SheetConfig::builtin();
+        match read_json::<SheetConfig>(SHEETS_PATH)? {
+            Some(user) => {
+                cfg.offset_x = user.offset_x;
+                cfg.offset_y = user.offset_y;
+                cfg.sheets.extend(user.sheets);
+            }
+            None => {
+                let _ = cfg.save();
+            }
+        }
+        Ok(cfg)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let _ = fs::create_dir_all("Setting");
+        fs::write(SHEETS_PATH, data).map_err(|e| format!("保存标签纸配置失败: {e}"))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.sheets.keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SheetTemplate> {
+        self.sheets.get(name)
+    }
+}
+
+/// The original fixed layout plus two common Avery stocks.
+fn builtin_sheets() -> BTreeMap<String, SheetTemplate> {
+    BTreeMap::from([
+        (
+            "A4 3×4".to_string(),
+            SheetTemplate {
+                page_width: 210.0,
+                page_height: 297.0,
+                rows: 4,
+                cols: 3,
+                cell_width: 55.0,
+                cell_height: 45.0,
+                gutter_x: 0.0,
+                gutter_y: 0.0,
+                margin_left: 10.0,
+                margin_top: 10.0,
+            },
+        ),
+        (
+            "A4 3×7 (Avery L7160)".to_string(),
+            SheetTemplate {
+                page_width: 210.0,
+                page_height: 297.0,
+                rows: 7,
+                cols: 3,
+                cell_width: 63.5,
+                cell_height: 38.1,
+                gutter_x: 2.5,
+                gutter_y: 0.0,
+                margin_left: 7.2,
+                margin_top: 15.15,
+            },
+        ),
+        (
+            "Letter 3×10 (Avery 5160)".to_string(),
+            SheetTemplate {
+                page_width: 215.9,
+                page_height: 279.4,
+                rows: 10,
+                cols: 3,
+                cell_width: 66.675,
+                cell_height: 25.4,
+                gutter_x: 3.175,
+                gutter_y: 0.0,
+                margin_left: 4.7625,
+                margin_top: 12.7,
+            },
+        ),
+    ])
+}
@@ -0,0 +1,219 @@
+//! Label layout templates.
+//!
+//! Each label type is described by a [`LabelTemplate`]: canvas size, where the
+//! barcode goes, and a list of text lines whose `{field}` placeholders are
+//! filled at render time (`{project}`, `{lot}`, `{prod_date}`,
+//! `{expire_date}`, `{test_counts}`). Templates are read from
+//! `Setting/label_templates.json`; the built-in set reproduces the original
+//! hardcoded layouts and is written out on first run so it can be edited.
+
+use crate::barcode::ImageType;
+use crate::config::read_json;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+const TEMPLATES_PATH: &str = "Setting/label_templates.json";
+
+/// Resolution template coordinates are given in: one pixel per dot of a
+/// 300 DPI printer.
+pub const TEMPLATE_DPI: u32 = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabelTemplate {
+    pub width: u32,
+    pub height: u32,
+    pub barcode: BarcodeBox,
+    pub texts: Vec<TextElement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BarcodeBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextElement {
+    /// Text with `{field}` placeholders.
+    pub text: String,
+    pub size: f32,
+    /// Baseline position in pixels.
+    pub y: i32,
+    /// Left/right anchor; ignored for centered text.
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub align: Align,
+    #[serde(default)]
+    pub bold: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+impl LabelTemplate {
+    /// The same layout for a `dpi` printer, all positions and sizes scaled
+    /// from [`TEMPLATE_DPI`].
+    pub fn scaled(&self, dpi: u32) -> LabelTemplate {
+        let f = dpi as f32 / TEMPLATE_DPI as f32;
+        let px = |v: u32| (v as f32 * f).round() as u32;
+        LabelTemplate {
+            width: px(self.width),
+            height: px(self.height),
+            barcode: BarcodeBox {
+                x: px(self.barcode.x),
+                y: px(self.barcode.y),
+                width: px(self.barcode.width),
+                height: px(self.barcode.height),
+            },
+            texts: self
+                .texts
+                .iter()
+                .map(|t| TextElement {
+                    size: t.size * f,
+                    y: (t.y as f32 * f).round() as i32,
+                    x: (t.x as f32 * f).round() as i32,
+                    ..t.clone()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TextElement {
+    /// Substitute `{name}` placeholders with the given values.
+    pub fn render(&self, fields: &[(&str, &str)]) -> String {
+        fields.iter().fold(self.text.clone(), |acc, (k, v)| {
+            acc.replace(&format!("{{{k}}}"), v)
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelTemplates {
+    templates: BTreeMap<String, LabelTemplate>,
+}
+
+/// The built-in layouts only.
+impl Default for LabelTemplates {
+    fn default() -> Self {
+        LabelTemplates {
+            templates: builtin_templates(),
+        }
+    }
+}
+
+impl LabelTemplates {
+    /// Load `Setting/label_templates.json`, filling any missing type from the
+    /// built-in set. A missing file is created from the built-ins; one that
+    /// cannot be parsed is an error naming the line and column.
+    pub fn load() -> Result<LabelTemplates, String> {
+        let mut templates = builtin_templates();
+        match read_json::<BTreeMap<String, LabelTemplate>>(TEMPLATES_PATH)? {
+            Some(user) => templates.extend(user),
+            None => {
+                if let Ok(data) = serde_json::to_string_pretty(&templates) {
+                    let _ = fs::create_dir_all("Setting");
+                    let _ = fs::write(TEMPLATES_PATH, data);
+                }
+            }
+        }
+        Ok(LabelTemplates { templates })
+    }
+
+    pub fn get(&self, image_type: ImageType) -> &LabelTemplate {
+        &self.templates[template_key(image_type)]
+    }
+}
+
+fn template_key(image_type: ImageType) -> &'static str {
+    match image_type {
+        ImageType::ReagentInformation => "reagent",
+        ImageType::ExcitationFluidA => "excitation_a",
+        ImageType::ExcitationFluidB => "excitation_b",
+        ImageType::QualityControl => "quality",
+        ImageType::CalibrationProduct => "calibration",
+    }
+}
+
+fn text(text: &str, size: f32, y: i32, bold: bool) -> TextElement {
+    TextElement {
+        text: text.into(),
+        size,
+        y,
+        x: 0,
+        align: Align::Center,
+        bold,
+    }
+}
+
+/// The original 660×580 layouts: title on top, optional subtitle lines,
+/// a 600×300 barcode, then lot / production / expiry footer.
+fn builtin(title: &str, subtitles: Vec<TextElement>, barcode_y: u32) -> LabelTemplate {
+    let footer_y = (barcode_y + 300 + 20) as i32;
+    let mut texts = vec![text(title, 32.0, 38, true)];
+    texts.extend(subtitles);
+    texts.extend([
+        text("产品批号: {lot}", 20.0, footer_y, false),
+        text("生产日期: {prod_date}", 20.0, footer_y + 40, false),
+        text("失效日期: {expire_date}", 20.0, footer_y + 80, false),
+    ]);
+    LabelTemplate {
+        width: 660,
+        height: 580,
+        barcode: BarcodeBox {
+            x: 30,
+            y: barcode_y,
+            width: 600,
+            height: 300,
+        },
+        texts,
+    }
+}
+
+fn builtin_templates() -> BTreeMap<String, LabelTemplate> {
+    let project_line = || vec![text("{project}", 24.0, 72, false)];
+    BTreeMap::from([
+        (
+            "reagent".to_string(),
+            builtin(
+                "试剂二维码信息",
+                vec![
+                    text("{project} 测定试剂盒", 22.0, 75, false),
+                    text(
+                        "(化学发光免疫分析法)  {test_counts} 测试/盒",
+                        18.0,
+                        100,
+                        false,
+                    ),
+                ],
+                130,
+            ),
+        ),
+        (
+            "excitation_a".to_string(),
+            builtin("激发液A二维码", vec![], 82),
+        ),
+        (
+            "excitation_b".to_string(),
+            builtin("激发液B二维码", vec![], 82),
+        ),
+        (
+            "quality".to_string(),
+            builtin("质控品二维码", project_line(), 98),
+        ),
+        (
+            "calibration".to_string(),
+            builtin("校准品二维码", project_line(), 98),
+        ),
+    ])
+}
@@ -0,0 +1,224 @@
+//! Field validation driven by the label spec in `Setting/化学发光条码信息.csv`.
+//!
+//! The spec is the same GBK-encoded sheet product management maintains, so
+//! rules are read from its free-text third column:
+//!
+//! - `范围：a-b` → integer within `a..=b`
+//! - `double` → floating point number
+//! - `int` / `只允许输入数字` → integer
+//! - anything else → must not be empty
+//!
+//! Fields named `*日期` must additionally be a `YYYY-MM-DD` date. Fields the
+//! sheet leaves to prose or omits (QC values, result parameters, counts) get
+//! their rules from [`SUPPLEMENT`], and a field with no rule at all is
+//! rejected rather than accepted as free text.
+
+use std::collections::HashMap;
+
+const SCHEMA_PATH: &str = "Setting/化学发光条码信息.csv";
+const BUILTIN_SCHEMA: &[u8] = include_bytes!("../../Setting/化学发光条码信息.csv");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    Text,
+    Date,
+    Integer { min: Option<i64>, max: Option<i64> },
+    Double,
+}
+
+/// Most labels one batch may generate.
+pub const MAX_QUANTITY: i64 = 1000;
+
+/// Rules the spec sheet does not state in a parsable form, by wire tag and
+/// spec key. They override whatever the sheet's wording parsed to.
+const SUPPLEMENT: &[(&str, &str, Rule)] = &[
+    ("reagent", "结果参数", Rule::Double),
+    ("qc", "Q1", Rule::Double),
+    ("qc", "SD1", Rule::Double),
+    ("qc", "Q2", Rule::Double),
+    ("qc", "SD2", Rule::Double),
+    (
+        "consumable",
+        "可用次数",
+        Rule::Integer {
+            min: Some(1),
+            max: Some(99999),
+        },
+    ),
+    (
+        "consumable",
+        "开瓶有效天数",
+        Rule::Integer {
+            min: Some(1),
+            max: Some(999),
+        },
+    ),
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    /// Keyed by wire tag (`reagent`, `calibration`, `qc`, `consumable`).
+    types: HashMap<String, HashMap<String, Rule>>,
+}
+
+impl Schema {
+    /// Load the spec from `Setting/`, falling back to the copy built into the binary.
+    pub fn load() -> Schema {
+        std::fs::read(SCHEMA_PATH)
+            .ok()
+            .map(|b| Schema::parse(&decode_text(&b)))
+            .filter(|s| !s.types.is_empty())
+            .unwrap_or_else(|| Schema::parse(&decode_text(BUILTIN_SCHEMA)))
+    }
+
+    pub fn parse(text: &str) -> Schema {
+        let mut types: HashMap<String, HashMap<String, Rule>> = HashMap::new();
+        let mut current: Option<String> = None;
+        for line in text.lines() {
+            let cols = split_csv_line(line);
+            let name = cols.get(1).map(|s| s.trim()).unwrap_or("");
+            let desc = cols.get(2).map(|s| s.trim()).unwrap_or("");
+            if name.is_empty() {
+                continue;
+            }
+            if name == "条码类型" {
+                current = Some(desc.to_string());
+                continue;
+            }
+            if let Some(tag) = &current {
+                types
+                    .entry(tag.clone())
+                    .or_default()
+                    .insert(name.to_string(), parse_rule(name, desc));
+            }
+        }
+        for (tag, fields) in types.iter_mut() {
+            for (t, key, rule) in SUPPLEMENT {
+                if t == tag {
+                    fields.insert(key.to_string(), rule.clone());
+                }
+            }
+            if let Some(quantity) = fields.get_mut("瓶数（生成个数）") {
+                *quantity = Rule::Integer {
+                    min: Some(1),
+                    max: Some(MAX_QUANTITY),
+                };
+            }
+        }
+        Schema { types }
+    }
+
+    pub fn rule(&self, tag: &str, key: &str) -> Option<Rule> {
+        self.types.get(tag).and_then(|t| t.get(key)).cloned()
+    }
+
+    /// Check `(label, spec_key, value)` triples for one label type.
+    ///
+    /// `label` is what the form shows, `spec_key` the field name in the spec
+    /// sheet. Every failing field is reported, not just the first.
+    pub fn validate(&self, tag: &str, fields: &[(&str, &str, &str)]) -> Result<(), String> {
+        let errors: Vec<String> = fields
+            .iter()
+            .filter_map(|(label, key, value)| match self.rule(tag, key) {
+                Some(rule) => check(&rule, label, value).err(),
+                None => Some(format!("「{label}」没有校验规则（规格表缺少“{key}”）")),
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("；"))
+        }
+    }
+}
+
+fn check(rule: &Rule, label: &str, value: &str) -> Result<(), String> {
+    let v = value.trim();
+    if v.is_empty() {
+        return Err(format!("「{label}」不能为空"));
+    }
+    match rule {
+        Rule::Text => Ok(()),
+        Rule::Date => chrono::NaiveDate::parse_from_str(v, "%Y-%m-%d")
+            .map(|_| ())
+            .map_err(|_| format!("「{label}」日期格式应为 YYYY-MM-DD")),
+        Rule::Double => v
+            .parse::<f64>()
+            .ok()
+            .filter(|f| f.is_finite())
+            .map(|_| ())
+            .ok_or_else(|| format!("「{label}」必须为数字")),
+        Rule::Integer { min, max } => {
+            let n: i64 = v
+                .parse()
+                .map_err(|_| format!("「{label}」只允许输入整数"))?;
+            if min.is_some_and(|m| n < m) || max.is_some_and(|m| n > m) {
+                return Err(format!(
+                    "「{label}」超出范围 ({}-{})",
+                    min.map(|m| m.to_string()).unwrap_or_default(),
+                    max.map(|m| m.to_string()).unwrap_or_default()
+                ));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn parse_rule(name: &str, desc: &str) -> Rule {
+    if let Some((min, max)) = parse_range(desc) {
+        return Rule::Integer {
+            min: Some(min),
+            max: Some(max),
+        };
+    }
+    if desc.contains("double") {
+        Rule::Double
+    } else if desc.contains("int") || desc.contains("只允许输入数字") {
+        Rule::Integer {
+            min: Some(0),
+            max: None,
+        }
+    } else if name.ends_with("日期") {
+        Rule::Date
+    } else {
+        Rule::Text
+    }
+}
+
+/// Extract `a-b` following `范围：` (full- or half-width colon).
+fn parse_range(desc: &str) -> Option<(i64, i64)> {
+    let idx = desc.find("范围")?;
+    let rest = desc[idx + "范围".len()..].trim_start_matches(['：', ':', ' ']);
+    let digits = |s: &str| -> String { s.chars().take_while(|c| c.is_ascii_digit()).collect() };
+    let lo = digits(rest);
+    let hi = digits(rest[lo.len()..].strip_prefix('-')?);
+    Some((lo.parse().ok()?, hi.parse().ok()?))
+}
+
+pub fn split_csv_line(line: &str) -> Vec<String> {
+    let mut cols = Vec::new();
+    let mut cur = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cur.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cols.push(std::mem::take(&mut cur)),
+            _ => cur.push(c),
+        }
+    }
+    cols.push(cur);
+    cols
+}
+
+/// The spec sheet is saved from Excel as GBK; accept UTF-8 too.
+pub fn decode_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.trim_start_matches('\u{feff}').to_string(),
+        Err(_) => encoding_rs::GBK.decode(bytes).0.into_owned(),
+    }
+}
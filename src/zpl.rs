@@ -0,0 +1,321 @@
+//! Zebra ZPL II 输出
+//!
+//! 把一批标签写成 `.zpl` 文本，可直接发送给热敏标签打印机。条码优先使用打印机
+//! 原生的 `^B7`（PDF417），无法原生表示时用 `^GF` 点阵图形兜底。坐标和尺寸单位
+//! 均为打印点（dots），换算由调用方按打印机 DPI 完成。
+
+use image::GrayImage;
+use std::fmt::Write;
+
+/// 常见热敏标签打印机的分辨率（DPI）
+pub const PRINTER_DPIS: [u32; 3] = [203, 300, 600];
+
+/// 一张标签
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub width: u32,
+    pub height: u32,
+    pub elements: Vec<Element>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// 元素方向，对应 ZPL 的 `N` `R` `I` `B`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Orientation {
+    /// 不旋转
+    #[default]
+    Normal,
+    /// 顺时针旋转 90°
+    Rotated,
+    /// 旋转 180°
+    Inverted,
+    /// 顺时针旋转 270°（自下而上读）
+    Bottom,
+}
+
+impl Orientation {
+    /// 顺时针旋转 `turns` 个 90°
+    pub fn from_quarter_turns(turns: usize) -> Orientation {
+        match turns % 4 {
+            0 => Orientation::Normal,
+            1 => Orientation::Rotated,
+            2 => Orientation::Inverted,
+            _ => Orientation::Bottom,
+        }
+    }
+
+    /// 旋转 90° 或 270°，宽高互换
+    pub fn is_sideways(self) -> bool {
+        matches!(self, Orientation::Rotated | Orientation::Bottom)
+    }
+
+    fn code(self) -> char {
+        match self {
+            Orientation::Normal => 'N',
+            Orientation::Rotated => 'R',
+            Orientation::Inverted => 'I',
+            Orientation::Bottom => 'B',
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Element {
+    /// 原生 PDF417：`^BY` 模块宽度 + `^B7`
+    Pdf417 {
+        x: u32,
+        y: u32,
+        /// 模块宽度（点）
+        module: u32,
+        /// 每行高度（点）
+        row_height: u32,
+        columns: u32,
+        /// 行数，0 表示由打印机决定
+        rows: u32,
+        /// 纠错等级 0-8
+        security: u32,
+        /// Compact（截断）PDF417
+        compact: bool,
+        orientation: Orientation,
+        data: String,
+    },
+    /// 点阵图形 `^GF`，灰度小于 128 的像素打印为黑色
+    Graphic { x: u32, y: u32, image: GrayImage },
+    /// 单行文字；`Center` / `Right` 在 `x..x+width` 的文本块内对齐
+    Text {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        align: TextAlign,
+        data: String,
+    },
+}
+
+/// PDF417 一行的模块数（不含静区）：起始符 17 + 左右指示符各 17 + 终止符 18，
+/// Compact 模式省去右指示符并把终止符缩成 1 个模块。
+pub fn pdf417_width_modules(columns: u32, compact: bool) -> u32 {
+    if compact {
+        17 * columns + 35
+    } else {
+        17 * columns + 69
+    }
+}
+
+/// 统计条码矩阵中的 PDF417 行数：相邻两行的码字必然不同，
+/// 所以相邻非空白像素行之间的每次变化都是新的一行。
+pub fn pdf417_rows(modules: &GrayImage) -> u32 {
+    let row = |y: u32| -> Vec<bool> {
+        (0..modules.width())
+            .map(|x| modules.get_pixel(x, y)[0] < 128)
+            .collect()
+    };
+    let mut rows = 0;
+    let mut prev: Option<Vec<bool>> = None;
+    for y in 0..modules.height() {
+        let cur = row(y);
+        if !cur.contains(&true) {
+            continue;
+        }
+        if prev.as_ref() != Some(&cur) {
+            rows += 1;
+        }
+        prev = Some(cur);
+    }
+    rows
+}
+
+/// 生成 ZPL 文本。`font` 为打印机字体：单个字符（如 `0`）表示内置字体，
+/// 否则视为字体文件名（如 `E:SIMSUN.TTF`），中文需要打印机上有对应字体。
+pub fn to_zpl(labels: &[Label], font: &str) -> String {
+    let mut out = String::new();
+    for label in labels {
+        out.push_str("^XA\n^CI28\n");
+        let _ = writeln!(out, "^PW{}\n^LL{}", label.width, label.height);
+        for el in &label.elements {
+            write_element(&mut out, el, font);
+        }
+        out.push_str("^XZ\n");
+    }
+    out
+}
+
+fn write_element(out: &mut String, el: &Element, font: &str) {
+    match el {
+        Element::Pdf417 {
+            x,
+            y,
+            module,
+            row_height,
+            columns,
+            rows,
+            security,
+            compact,
+            orientation,
+            data,
+        } => {
+            let rows = if *rows == 0 {
+                String::new()
+            } else {
+                rows.to_string()
+            };
+            let _ = writeln!(
+                out,
+                "^FO{x},{y}^BY{module}^B7{},{row_height},{security},{columns},{rows},{}^FH^FD{}^FS",
+                orientation.code(),
+                if *compact { 'Y' } else { 'N' },
+                escape(data)
+            );
+        }
+        Element::Graphic { x, y, image } => {
+            let row_bytes = image.width().div_ceil(8);
+            let total = row_bytes * image.height();
+            let _ = write!(out, "^FO{x},{y}^GFA,{total},{total},{row_bytes},");
+            for py in 0..image.height() {
+                for bx in 0..row_bytes {
+                    let mut byte = 0u8;
+                    for bit in 0..8 {
+                        let px = bx * 8 + bit;
+                        if px < image.width() && image.get_pixel(px, py)[0] < 128 {
+                            byte |= 0x80 >> bit;
+                        }
+                    }
+                    let _ = write!(out, "{byte:02X}");
+                }
+            }
+            out.push_str("^FS\n");
+        }
+        Element::Text {
+            x,
+            y,
+            width,
+            height,
+            align,
+            data,
+        } => {
+            let _ = write!(out, "^FO{x},{y}");
+            if font.chars().count() == 1 {
+                let _ = write!(out, "^A{font}N,{height},{height}");
+            } else {
+                let _ = write!(out, "^A@N,{height},{height},{font}");
+            }
+            let justify = match align {
+                TextAlign::Left => None,
+                TextAlign::Center => Some('C'),
+                TextAlign::Right => Some('R'),
+            };
+            if let Some(j) = justify {
+                let _ = write!(out, "^FB{width},1,0,{j}");
+            }
+            let _ = writeln!(out, "^FH^FD{}^FS", escape(data));
+        }
+    }
+}
+
+/// `^FH` 十六进制转义：`^` `~` 是命令前缀，`_` 是转义符本身。
+fn escape(data: &str) -> String {
+    let mut out = String::with_capacity(data.len());
+    for c in data.chars() {
+        match c {
+            '^' => out.push_str("_5E"),
+            '~' => out.push_str("_7E"),
+            '_' => out.push_str("_5F"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::assert_golden;
+
+    #[test]
+    fn test_native_text() {
+        let label = Label {
+            width: 660,
+            height: 580,
+            elements: vec![
+                Element::Text {
+                    x: 0,
+                    y: 8,
+                    width: 660,
+                    height: 32,
+                    align: TextAlign::Center,
+                    data: "质控品二维码".into(),
+                },
+                Element::Pdf417 {
+                    x: 30,
+                    y: 98,
+                    module: 5,
+                    row_height: 15,
+                    columns: 3,
+                    rows: 20,
+                    security: 0,
+                    compact: false,
+                    orientation: Orientation::Normal,
+                    data: "RL2.k20260101.AbC+/9=".into(),
+                },
+                Element::Text {
+                    x: 20,
+                    y: 402,
+                    width: 640,
+                    height: 20,
+                    align: TextAlign::Left,
+                    data: "产品批号: L_01^".into(),
+                },
+            ],
+        };
+        assert_golden("native_text.zpl", &to_zpl(&[label], "E:SIMSUN.TTF"));
+    }
+
+    #[test]
+    fn test_pdf417_orientations() {
+        let labels: Vec<Label> = (0..4)
+            .map(|turns| Label {
+                width: 472,
+                height: 236,
+                elements: vec![Element::Pdf417 {
+                    x: 0,
+                    y: 0,
+                    module: 3,
+                    row_height: 9,
+                    columns: 5,
+                    rows: 0,
+                    security: 2,
+                    compact: turns % 2 == 1,
+                    orientation: Orientation::from_quarter_turns(turns),
+                    data: "A03157G71084UN2431122026".into(),
+                }],
+            })
+            .collect();
+        assert_golden("orientations.zpl", &to_zpl(&labels, "0"));
+    }
+
+    #[test]
+    fn test_graphic_fallback() {
+        // 10×2：第一行左起 9 个黑点，第二行全白，验证跨字节填充
+        let image = GrayImage::from_fn(10, 2, |x, y| {
+            image::Luma([if y == 0 && x < 9 { 0 } else { 255 }])
+        });
+        let label = Label {
+            width: 10,
+            height: 2,
+            elements: vec![Element::Graphic { x: 0, y: 0, image }],
+        };
+        assert_golden("graphic.zpl", &to_zpl(&[label], "0"));
+    }
+
+    #[test]
+    fn test_pdf417_width_modules() {
+        assert_eq!(pdf417_width_modules(3, false), 120);
+        assert_eq!(pdf417_width_modules(2, true), 69);
+    }
+}
@@ -0,0 +1,21 @@
+//! Golden-file comparison shared by the library and binary unit tests; each
+//! crate root includes this file with `#[path]`.
+
+/// Compare with the file under `tests/golden/`; `UPDATE_GOLDEN=1` rewrites it.
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).unwrap();
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap()
+        .replace("\r\n", "\n");
+    assert_eq!(
+        actual,
+        expected,
+        "{} differs from the golden file",
+        path.display()
+    );
+}